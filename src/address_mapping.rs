@@ -0,0 +1,164 @@
+use std::net::IpAddr;
+
+use crate::constants::*;
+use crate::errors::*;
+use crate::ip_prefix::*;
+use crate::parsed_packet::*;
+use crate::rr_iterator::*;
+use crate::synth::r#gen;
+
+/// An `AddressRule` maps the addresses of a source prefix to the addresses of
+/// a target prefix with the same length, keeping their host bits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AddressRule {
+    pub source: IpPrefix,
+    pub target: IpPrefix,
+}
+
+impl AddressRule {
+    /// Creates a rule mapping `source` to `target`, that must have the same
+    /// family and length.
+    pub fn new(source: IpPrefix, target: IpPrefix) -> Result<Self, Error> {
+        if source.is_ipv4() != target.is_ipv4() {
+            bail!(DSError::WrongAddressFamily);
+        }
+        if source.prefix_len() != target.prefix_len() {
+            bail!(DSError::InvalidPrefix(
+                "Source and target prefixes must have the same length"
+            ));
+        }
+        Ok(AddressRule { source, target })
+    }
+}
+
+/// An `AddressMapping` rewrites addresses from `A` and `AAAA` records
+/// according to a table of rules, for example to replace public addresses
+/// with internal ones behind a NAT.
+///
+/// When several rules match an address, the one with the longest source
+/// prefix is used.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AddressMapping {
+    pub rules: Vec<AddressRule>,
+}
+
+impl AddressMapping {
+    /// Creates an empty mapping.
+    pub fn new() -> Self {
+        AddressMapping::default()
+    }
+
+    /// Loads a table of rules, with one `source target` pair of prefixes per
+    /// line, such as `203.0.113.0/24 10.0.0.0/24`. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn from_table(table: &str) -> Result<Self, Error> {
+        let mut address_mapping = AddressMapping::new();
+        for line in table.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut prefixes = line.split_whitespace();
+            let (source, target) = match (prefixes.next(), prefixes.next(), prefixes.next()) {
+                (Some(source), Some(target), None) => (source.parse()?, target.parse()?),
+                _ => bail!(DSError::InvalidPrefix(
+                    "Rules must have a source and a target"
+                )),
+            };
+            address_mapping.add_rule(source, target)?;
+        }
+        Ok(address_mapping)
+    }
+
+    /// Adds a rule mapping `source` to `target`.
+    pub fn add_rule(&mut self, source: IpPrefix, target: IpPrefix) -> Result<(), Error> {
+        self.rules.push(AddressRule::new(source, target)?);
+        Ok(())
+    }
+
+    /// Returns the address `ip` is mapped to, or `None` if no rule matches.
+    pub fn map(&self, ip: &IpAddr) -> Option<IpAddr> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.source.contains(ip))
+            .max_by_key(|rule| rule.source.prefix_len())
+            .and_then(|rule| rule.source.translate(ip, &rule.target))
+    }
+
+    /// Returns the address `ip` was mapped from, or `None` if no rule
+    /// matches.
+    pub fn unmap(&self, ip: &IpAddr) -> Option<IpAddr> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.target.contains(ip))
+            .max_by_key(|rule| rule.target.prefix_len())
+            .and_then(|rule| rule.target.translate(ip, &rule.source))
+    }
+
+    /// Rewrites the addresses of all the `A` and `AAAA` records from the
+    /// answer and additional sections, and returns the number of rewritten
+    /// records.
+    pub fn apply(&self, parsed_packet: &mut ParsedPacket) -> Result<usize, Error> {
+        let mut rewritten = 0;
+        let mut it = parsed_packet.try_into_iter_answer()?;
+        while let Some(mut item) = it {
+            if let Some(ip) = item.rr_ip().ok().and_then(|ip| self.map(&ip)) {
+                item.set_rr_ip(&ip)?;
+                rewritten += 1;
+            }
+            it = item.next();
+        }
+        let mut it = parsed_packet.try_into_iter_additional()?;
+        while let Some(mut item) = it {
+            if let Some(ip) = item.rr_ip().ok().and_then(|ip| self.map(&ip)) {
+                item.set_rr_ip(&ip)?;
+                rewritten += 1;
+            }
+            it = item.next();
+        }
+        Ok(rewritten)
+    }
+
+    /// Rewrites a `PTR` query for the reverse name of a mapped address into
+    /// a query for the reverse name of the original address. Returns `false`
+    /// if this is not such a query.
+    ///
+    /// The response can be rewritten back with `rewrite_ptr_response()`.
+    pub fn rewrite_ptr_query(&self, query: &mut ParsedPacket) -> Result<bool, Error> {
+        Self::rename_ptr_question(query, |ip| self.unmap(ip))
+    }
+
+    /// Rewrites a response to a `PTR` query for the reverse name of an
+    /// address that is mapped into a response for the reverse name of the
+    /// mapped address. Returns `false` if this is not such a response.
+    pub fn rewrite_ptr_response(&self, response: &mut ParsedPacket) -> Result<bool, Error> {
+        Self::rename_ptr_question(response, |ip| self.map(ip))
+    }
+
+    /// Replaces the question name of a `PTR` query or response, and all the
+    /// names matching it, with the reverse name of the address returned by
+    /// `f`.
+    fn rename_ptr_question<F>(parsed_packet: &mut ParsedPacket, f: F) -> Result<bool, Error>
+    where
+        F: Fn(&IpAddr) -> Option<IpAddr>,
+    {
+        let (name, rr_type, _) = match parsed_packet.question() {
+            None => return Ok(false),
+            Some(question) => question,
+        };
+        if rr_type != Type::PTR.into() {
+            return Ok(false);
+        }
+        let ip = match reverse_name_to_ip(&name).and_then(|ip| f(&ip)) {
+            None => return Ok(false),
+            Some(ip) => ip,
+        };
+        let source_name = match parsed_packet.question_raw0() {
+            None => return Ok(false),
+            Some((source_name, ..)) => source_name.to_vec(),
+        };
+        let target_name = r#gen::raw_name_from_str(&reverse_name(&ip), None)?;
+        parsed_packet.rename_with_raw_names(&target_name, &source_name, false)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,624 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::constants::*;
+use crate::dns_sector::*;
+use crate::errors::*;
+use crate::parsed_packet::*;
+use crate::rr_iterator::*;
+
+/// TTL of stale responses recommended by RFC 8767 section 4.
+pub const DEFAULT_STALE_TTL: u32 = 30;
+
+/// Size of the fixed part of an EDNS Client Subnet option (RFC 7871), before
+/// the address.
+const CLIENT_SUBNET_FIXED_SIZE: usize = 4;
+
+/// A source of time for the cache, in seconds.
+pub trait Clock {
+    /// Returns the current time, in seconds.
+    fn now(&self) -> u64;
+}
+
+/// The system clock.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Creates a clock set to `now`.
+    pub fn new(now: u64) -> Self {
+        ManualClock {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    /// Sets the current time.
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::Relaxed)
+    }
+
+    /// Moves the clock forward by `secs` seconds.
+    pub fn advance(&self, secs: u64) {
+        self.now.fetch_add(secs, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+/// Which entry is evicted when the cache is full.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EvictionPolicy {
+    /// The entry that was inserted or returned the longest time ago.
+    LeastRecentlyUsed,
+    /// The entry that expires first.
+    SoonestExpiring,
+}
+
+/// A `CacheOptions` structure describes what a `Cache` stores, and for how
+/// long.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CacheOptions {
+    /// Maximum number of entries.
+    pub capacity: usize,
+    /// Minimum TTL of cached responses.
+    pub min_ttl: u32,
+    /// Maximum TTL of cached responses.
+    pub max_ttl: u32,
+    /// Maximum TTL of cached negative responses (RFC 2308 section 5).
+    pub max_negative_ttl: u32,
+    /// Whether responses are cached per EDNS client subnet, using the scope
+    /// prefix returned by the server (RFC 7871 section 7.3).
+    pub client_subnet: bool,
+    /// Which entry is evicted when the cache is full.
+    pub eviction_policy: EvictionPolicy,
+    /// How long expired responses are kept, to be served stale (RFC 8767).
+    /// `0` disables serve-stale.
+    pub stale_window: u32,
+    /// TTL of the records of stale responses.
+    pub stale_ttl: u32,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            capacity: 4096,
+            min_ttl: 0,
+            max_ttl: 86400,
+            max_negative_ttl: 10800,
+            client_subnet: false,
+            eviction_policy: EvictionPolicy::LeastRecentlyUsed,
+            stale_window: 0,
+            stale_ttl: DEFAULT_STALE_TTL,
+        }
+    }
+}
+
+impl CacheOptions {
+    /// Sets the maximum number of entries.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the range of TTLs of cached responses.
+    pub fn with_ttls(mut self, min_ttl: u32, max_ttl: u32) -> Self {
+        self.min_ttl = min_ttl;
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// Sets the maximum TTL of cached negative responses.
+    pub fn with_max_negative_ttl(mut self, max_negative_ttl: u32) -> Self {
+        self.max_negative_ttl = max_negative_ttl;
+        self
+    }
+
+    /// Sets whether responses are cached per EDNS client subnet.
+    pub fn with_client_subnet(mut self, client_subnet: bool) -> Self {
+        self.client_subnet = client_subnet;
+        self
+    }
+
+    /// Sets the eviction policy.
+    pub fn with_eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// Keeps expired responses for `stale_window` seconds, to be served with
+    /// a TTL of `stale_ttl` seconds.
+    pub fn with_serve_stale(mut self, stale_window: u32, stale_ttl: u32) -> Self {
+        self.stale_window = stale_window;
+        self.stale_ttl = stale_ttl;
+        self
+    }
+}
+
+/// The freshness of a cached response.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Staleness {
+    /// The response expires in `ttl` seconds.
+    Fresh { ttl: u32 },
+    /// The response expired `expired_for` seconds ago, and can be served
+    /// stale for another `remaining` seconds. It should be refreshed.
+    Stale { expired_for: u32, remaining: u32 },
+}
+
+impl Staleness {
+    /// Returns `true` if the response has expired.
+    #[inline]
+    pub fn is_stale(&self) -> bool {
+        matches!(self, Staleness::Stale { .. })
+    }
+}
+
+/// A client subnet, with the address truncated to the prefix length.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ClientSubnet {
+    pub family: u16,
+    pub prefix: u8,
+    pub address: Vec<u8>,
+}
+
+impl ClientSubnet {
+    /// Creates a client subnet, keeping only the first `prefix` bits of
+    /// `address`.
+    pub fn new(family: u16, prefix: u8, address: &[u8]) -> Self {
+        let prefix = prefix.min((address.len() * 8).min(0xff) as u8);
+        let mut address = address[..(prefix as usize).div_ceil(8)].to_vec();
+        let last_bits = prefix % 8;
+        if last_bits != 0 {
+            if let Some(last) = address.last_mut() {
+                *last &= 0xff << (8 - last_bits);
+            }
+        }
+        ClientSubnet {
+            family,
+            prefix,
+            address,
+        }
+    }
+}
+
+/// The content of an EDNS Client Subnet option.
+struct ClientSubnetOption {
+    family: u16,
+    source_prefix: u8,
+    scope_prefix: u8,
+    address: Vec<u8>,
+}
+
+impl ClientSubnetOption {
+    /// Returns the EDNS Client Subnet option of `parsed_packet`, if there is
+    /// one.
+    fn from_packet(parsed_packet: &ParsedPacket) -> Option<Self> {
+        let packet = parsed_packet.packet();
+        let mut offset = parsed_packet.offset_edns?;
+        for _ in 0..parsed_packet.edns_count {
+            let code = BigEndian::read_u16(&packet[offset + DNS_EDNS_RR_CODE_OFFSET..]);
+            let len = BigEndian::read_u16(&packet[offset + DNS_EDNS_RR_RDLEN_OFFSET..]) as usize;
+            if code == EdnsOption::CLIENT_SUBNET.into() && len >= CLIENT_SUBNET_FIXED_SIZE {
+                let data = &packet[offset + DNS_EDNS_RR_HEADER_SIZE..][..len];
+                return Some(ClientSubnetOption {
+                    family: BigEndian::read_u16(data),
+                    source_prefix: data[2],
+                    scope_prefix: data[3],
+                    address: data[CLIENT_SUBNET_FIXED_SIZE..].to_vec(),
+                });
+            }
+            offset = RRIterator::edns_skip_rr(packet, offset);
+        }
+        None
+    }
+}
+
+/// The key of a cached response.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CacheKey {
+    /// Lowercased question name, in raw format, including the trailing `0`.
+    pub name: Vec<u8>,
+    pub qtype: u16,
+    pub qclass: u16,
+    pub dnssec_ok: bool,
+    pub checking_disabled: bool,
+    /// Client subnet the response applies to, if it doesn't apply to all
+    /// clients.
+    pub client_subnet: Option<ClientSubnet>,
+}
+
+impl CacheKey {
+    /// Returns the key of `query`, regardless of its client subnet, or
+    /// `None` if it doesn't have a question.
+    pub fn from_query(query: &mut ParsedPacket) -> Option<Self> {
+        let dnssec_ok = query.dnssec();
+        let checking_disabled = query.cd();
+        let (name, qtype, qclass) = query.question_raw0()?;
+        Some(CacheKey {
+            name: name.to_ascii_lowercase(),
+            qtype,
+            qclass,
+            dnssec_ok,
+            checking_disabled,
+            client_subnet: None,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    response: ParsedPacket,
+    inserted_at: u64,
+    expires_at: u64,
+    order: (u64, u64),
+}
+
+/// A bounded cache of responses.
+///
+/// Responses are stored along with the time they were inserted at, and the
+/// TTLs of the returned copies are decremented accordingly. Negative
+/// responses are cached for the duration given by their SOA record (RFC
+/// 2308), and are not cached at all without one. Expired responses can be
+/// kept for a while, and served stale (RFC 8767) by `lookup_stale()`.
+#[derive(Debug)]
+pub struct Cache<C: Clock = SystemClock> {
+    options: CacheOptions,
+    clock: C,
+    entries: HashMap<CacheKey, CacheEntry>,
+    order: BTreeMap<(u64, u64), CacheKey>,
+    stamp: u64,
+    /// Number of entries for each client subnet family and scope prefix.
+    client_subnet_scopes: BTreeMap<(u16, u8), usize>,
+}
+
+impl Cache<SystemClock> {
+    /// Creates a cache using the system clock.
+    pub fn new(options: CacheOptions) -> Self {
+        Self::with_clock(options, SystemClock)
+    }
+}
+
+impl<C: Clock> Cache<C> {
+    /// Creates a cache using `clock`.
+    pub fn with_clock(options: CacheOptions, clock: C) -> Self {
+        Cache {
+            options,
+            clock,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            stamp: 0,
+            client_subnet_scopes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the clock.
+    #[inline]
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Returns the number of entries, including expired entries that haven't
+    /// been removed yet, and stale entries.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the cache is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the client subnet families and scope prefixes that cached
+    /// entries apply to, as `(family, scope_prefix)` pairs.
+    pub fn client_subnet_scopes(&self) -> Vec<(u16, u8)> {
+        self.client_subnet_scopes.keys().copied().collect()
+    }
+
+    /// Removes all the entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.client_subnet_scopes.clear();
+    }
+
+    /// Stores `response`, the response to `query`.
+    ///
+    /// Returns `false` if the response cannot be cached: truncated responses,
+    /// responses with a return code other than `NOERROR` or `NXDOMAIN`,
+    /// negative responses without a SOA record, and responses with a zero
+    /// TTL.
+    pub fn insert(
+        &mut self,
+        query: &mut ParsedPacket,
+        response: &ParsedPacket,
+    ) -> Result<bool, Error> {
+        if self.options.capacity == 0 {
+            return Ok(false);
+        }
+        let mut response = response.clone();
+        response.validate()?;
+        if !response.is_response() || response.tc() {
+            return Ok(false);
+        }
+        let negative = match response.typed_rcode() {
+            Rcode::NXDOMAIN => true,
+            Rcode::NOERROR => DNSSector::ancount(response.packet()) == 0,
+            _ => return Ok(false),
+        };
+        if negative && !Self::has_soa(&mut response)? {
+            return Ok(false);
+        }
+        let max_ttl = if negative {
+            self.options.max_ttl.min(self.options.max_negative_ttl)
+        } else {
+            self.options.max_ttl
+        };
+        let ttl = match response.min_ttl()? {
+            None => return Ok(false),
+            Some(ttl) => ttl.max(self.options.min_ttl).min(max_ttl),
+        };
+        if ttl == 0 {
+            return Ok(false);
+        }
+        response.clamp_ttls(self.options.min_ttl, max_ttl)?;
+        let mut key = match CacheKey::from_query(query) {
+            None => return Ok(false),
+            Some(key) => key,
+        };
+        if self.options.client_subnet {
+            key.client_subnet = Self::response_client_subnet(query, &response);
+        }
+
+        self.remove(&key);
+        if self.entries.len() >= self.options.capacity {
+            if let Some((_, evicted_key)) = self.order.pop_first() {
+                self.remove(&evicted_key);
+            }
+        }
+        if let Some(client_subnet) = &key.client_subnet {
+            *self
+                .client_subnet_scopes
+                .entry((client_subnet.family, client_subnet.prefix))
+                .or_default() += 1;
+        }
+        let now = self.clock.now();
+        let expires_at = now + ttl as u64;
+        let order = self.next_order(expires_at);
+        self.order.insert(order, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                response,
+                inserted_at: now,
+                expires_at,
+                order,
+            },
+        );
+        Ok(true)
+    }
+
+    /// Returns a copy of the cached response to `query`, if there is one and
+    /// it hasn't expired.
+    ///
+    /// TTLs are decremented by the time elapsed since the response was
+    /// stored, and the transaction ID and the case of the question are the
+    /// ones from `query`.
+    pub fn lookup(&mut self, query: &mut ParsedPacket) -> Option<ParsedPacket> {
+        let (key, staleness) = self.find(query, false)?;
+        self.copy_response(&key, staleness, query)
+    }
+
+    /// Returns a copy of the cached response to `query` like `lookup()`
+    /// does, but also returns expired responses within the stale window.
+    ///
+    /// Stale responses are prepared with `ParsedPacket::mark_stale()`. The
+    /// returned `Staleness` tells whether the entry should be refreshed.
+    pub fn lookup_stale(&mut self, query: &mut ParsedPacket) -> Option<(ParsedPacket, Staleness)> {
+        let (key, staleness) = self.find(query, true)?;
+        let response = self.copy_response(&key, staleness, query)?;
+        Some((response, staleness))
+    }
+
+    /// Returns the freshness of the cached response to `query`, if there is
+    /// one.
+    pub fn staleness(&mut self, query: &mut ParsedPacket) -> Option<Staleness> {
+        self.find(query, true).map(|(_, staleness)| staleness)
+    }
+
+    /// Returns the key and the freshness of the entry matching `query`.
+    /// Entries past the stale window are removed.
+    fn find(
+        &mut self,
+        query: &mut ParsedPacket,
+        allow_stale: bool,
+    ) -> Option<(CacheKey, Staleness)> {
+        let key = CacheKey::from_query(query)?;
+        let mut keys = vec![];
+        if self.options.client_subnet {
+            if let Some(ecs) = ClientSubnetOption::from_packet(query) {
+                for &(family, scope_prefix) in self.client_subnet_scopes.keys().rev() {
+                    if family == ecs.family && scope_prefix <= ecs.source_prefix {
+                        let mut key = key.clone();
+                        key.client_subnet =
+                            Some(ClientSubnet::new(family, scope_prefix, &ecs.address));
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+        keys.push(key);
+
+        let now = self.clock.now();
+        for key in keys {
+            let expires_at = match self.entries.get(&key) {
+                None => continue,
+                Some(entry) => entry.expires_at,
+            };
+            match self.entry_staleness(expires_at, now) {
+                None => {
+                    self.remove(&key);
+                }
+                Some(staleness) if staleness.is_stale() && !allow_stale => {}
+                Some(staleness) => return Some((key, staleness)),
+            }
+        }
+        None
+    }
+
+    /// Returns the freshness of an entry, or `None` if it is past the stale
+    /// window.
+    fn entry_staleness(&self, expires_at: u64, now: u64) -> Option<Staleness> {
+        if now < expires_at {
+            let ttl = (expires_at - now).min(u32::MAX as u64) as u32;
+            return Some(Staleness::Fresh { ttl });
+        }
+        let expired_for = now - expires_at;
+        let stale_window = self.options.stale_window as u64;
+        if expired_for >= stale_window {
+            return None;
+        }
+        Some(Staleness::Stale {
+            expired_for: expired_for as u32,
+            remaining: (stale_window - expired_for) as u32,
+        })
+    }
+
+    /// Returns a copy of the response stored for `key`, adjusted for `query`.
+    fn copy_response(
+        &mut self,
+        key: &CacheKey,
+        staleness: Staleness,
+        query: &mut ParsedPacket,
+    ) -> Option<ParsedPacket> {
+        self.touch(key);
+        let entry = &self.entries[key];
+        let mut response = entry.response.clone();
+        match staleness {
+            Staleness::Fresh { .. } => {
+                let elapsed = self.clock.now().saturating_sub(entry.inserted_at);
+                let elapsed = elapsed.min(u32::MAX as u64) as u32;
+                response.decrement_ttls(elapsed).ok()?;
+            }
+            Staleness::Stale { .. } => response.mark_stale(self.options.stale_ttl).ok()?,
+        }
+        response.set_tid(query.tid());
+        let (query_name, _, _) = query.question_raw0()?;
+        if !response.echoes_question_case(query_name) {
+            let query_name = query_name.to_vec();
+            response.restore_question_case(&query_name).ok()?;
+        }
+        Some(response)
+    }
+
+    /// Removes the entry for `key`. Returns `true` if there was one.
+    pub fn remove(&mut self, key: &CacheKey) -> bool {
+        match self.entries.remove(key) {
+            None => false,
+            Some(entry) => {
+                self.order.remove(&entry.order);
+                if let Some(client_subnet) = &key.client_subnet {
+                    let scope = (client_subnet.family, client_subnet.prefix);
+                    if let Some(count) = self.client_subnet_scopes.get_mut(&scope) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.client_subnet_scopes.remove(&scope);
+                        }
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    /// Removes all the entries that expired and are past the stale window,
+    /// and returns how many were removed.
+    pub fn remove_expired(&mut self) -> usize {
+        let now = self.clock.now();
+        let expired: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| self.entry_staleness(entry.expires_at, now).is_none())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+
+    /// Returns the position of a new entry in the eviction order.
+    fn next_order(&mut self, expires_at: u64) -> (u64, u64) {
+        self.stamp += 1;
+        match self.options.eviction_policy {
+            EvictionPolicy::LeastRecentlyUsed => (self.stamp, 0),
+            EvictionPolicy::SoonestExpiring => (expires_at, self.stamp),
+        }
+    }
+
+    /// Marks an entry as used.
+    fn touch(&mut self, key: &CacheKey) {
+        if self.options.eviction_policy != EvictionPolicy::LeastRecentlyUsed {
+            return;
+        }
+        let order = self.next_order(0);
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.order.remove(&entry.order);
+            entry.order = order;
+            self.order.insert(order, key.clone());
+        }
+    }
+
+    /// Returns `true` if the name servers section includes a SOA record.
+    fn has_soa(response: &mut ParsedPacket) -> Result<bool, Error> {
+        let mut it = response.try_into_iter_nameservers()?;
+        while let Some(item) = it {
+            if item.rr_type() == Type::SOA.into() {
+                return Ok(true);
+            }
+            it = item.next();
+        }
+        Ok(false)
+    }
+
+    /// Returns the client subnet a response applies to, or `None` if it
+    /// applies to all clients.
+    fn response_client_subnet(
+        query: &ParsedPacket,
+        response: &ParsedPacket,
+    ) -> Option<ClientSubnet> {
+        let query_ecs = ClientSubnetOption::from_packet(query)?;
+        let response_ecs = ClientSubnetOption::from_packet(response)?;
+        if response_ecs.scope_prefix == 0 || response_ecs.family != query_ecs.family {
+            return None;
+        }
+        let scope_prefix = response_ecs.scope_prefix.min(query_ecs.source_prefix);
+        Some(ClientSubnet::new(
+            query_ecs.family,
+            scope_prefix,
+            &query_ecs.address,
+        ))
+    }
+}
@@ -5,6 +5,7 @@ use byteorder::{BigEndian, ByteOrder};
 use crate::constants::*;
 use crate::dns_sector::*;
 use crate::errors::*;
+use crate::parse_options::*;
 use crate::rr_iterator::*;
 
 /// Output of the `copy_uncompressed_name()` function.
@@ -28,40 +29,72 @@ impl Compress {
     /// following indirections for compressed names, checks for label
     /// lengths, checks for truncated names and checks for cycles.
     /// Returns the location right after the name.
-    pub fn check_compressed_name(packet: &[u8], mut offset: usize) -> Result<usize, Error> {
+    ///
+    /// Errors are `ParseError`s carrying the offset of the offending label.
+    pub fn check_compressed_name(packet: &[u8], offset: usize) -> Result<usize, Error> {
+        Self::check_compressed_name_with_max_indirections(
+            packet,
+            offset,
+            DNS_MAX_HOSTNAME_INDIRECTIONS,
+        )
+    }
+
+    /// Checks that an encoded DNS name is valid, like
+    /// `check_compressed_name()` does, following at most `max_indirections`
+    /// compression pointers.
+    ///
+    /// Longer chains are rejected with `DSError::LimitExceeded`, unless
+    /// `max_indirections` is `DNS_MAX_HOSTNAME_INDIRECTIONS`, in which case
+    /// they are invalid names, as with `check_compressed_name()`.
+    pub fn check_compressed_name_with_max_indirections(
+        packet: &[u8],
+        mut offset: usize,
+        max_indirections: u16,
+    ) -> Result<usize, Error> {
         let packet_len = packet.len();
         let mut name_len = 0;
         let (mut barrier_offset, mut lowest_offset, mut final_offset) = (packet_len, offset, None);
-        let mut refs_allowed = DNS_MAX_HOSTNAME_INDIRECTIONS;
+        let mut refs_allowed = max_indirections;
         if offset >= packet_len {
-            bail!(DSError::InternalError("Offset outside packet boundaries"));
+            bail!(ParseError::new(
+                DSError::InternalError("Offset outside packet boundaries"),
+                offset
+            )
+            .into_error());
         }
         if 1 > packet_len - offset {
-            bail!(DSError::InvalidName("Empty name"));
+            bail!(Self::invalid_name("Empty name", offset))
         }
         loop {
             if offset >= barrier_offset {
                 if offset >= packet_len {
-                    bail!(DSError::InvalidName("Truncated name"));
+                    bail!(Self::invalid_name("Truncated name", offset))
                 }
-                bail!(DSError::InvalidName("Cycle"));
+                bail!(Self::invalid_name("Cycle", offset))
             }
             let label_len = match packet[offset] {
                 len if len & 0xc0 == 0xc0 => {
                     if refs_allowed <= 0 {
-                        bail!(DSError::InvalidName("Too many indirections"));
+                        if max_indirections == DNS_MAX_HOSTNAME_INDIRECTIONS {
+                            bail!(Self::invalid_name("Too many indirections", offset))
+                        }
+                        bail!(ParseError::new(
+                            DSError::LimitExceeded("Too many indirections"),
+                            offset
+                        )
+                        .into_error())
                     }
                     refs_allowed -= 1;
                     if 2 > packet_len - offset {
-                        bail!(DSError::InvalidName("Invalid internal offset"));
+                        bail!(Self::invalid_name("Invalid internal offset", offset))
                     }
                     let ref_offset =
                         ((((len & 0x3f) as u16) << 8) | (packet[offset + 1]) as u16) as usize;
                     if ref_offset == offset || ref_offset >= lowest_offset {
-                        bail!(DSError::InvalidName("Forward/self reference"));
+                        bail!(Self::invalid_name("Forward/self reference", offset))
                     }
                     if packet[ref_offset] & 0xc0 != 0xc0 && packet[ref_offset] < 1 {
-                        bail!(DSError::InvalidName("Reference to an empty label"));
+                        bail!(Self::invalid_name("Reference to an empty label", offset))
                     }
                     final_offset = final_offset.or(Some(offset + 2));
                     offset = ref_offset;
@@ -69,21 +102,23 @@ impl Compress {
                     lowest_offset = ref_offset;
                     continue;
                 }
-                len if len > 0x3f => bail!(DSError::InvalidName("Label length too long")),
+                len if len > 0x3f => {
+                    bail!(Self::invalid_name("Label length too long", offset))
+                }
                 len => len as usize,
             };
             if label_len >= packet_len - offset {
-                bail!(DSError::InvalidName("Out-of-bounds name"));
+                bail!(Self::invalid_name("Out-of-bounds name", offset))
             }
             name_len += label_len + 1;
             if name_len > DNS_MAX_HOSTNAME_LEN {
-                bail!(DSError::InvalidName("Name too long"));
+                bail!(Self::invalid_name("Name too long", offset))
             }
             if packet[offset + 1..offset + label_len + 1]
                 .iter()
                 .any(|&c| c.is_ascii_control() || c == b'.' || c == b'\\' || c == 0)
             {
-                bail!(DSError::InvalidName("Unexpected character in name"));
+                bail!(Self::invalid_name("Unexpected character in name", offset))
             }
             offset += label_len + 1;
             if label_len == 0 {
@@ -94,6 +129,13 @@ impl Compress {
         Ok(final_offset)
     }
 
+    /// Builds the error returned by `check_compressed_name()` for a name
+    /// rejected at `offset`.
+    #[inline]
+    fn invalid_name(reason: &'static str, offset: usize) -> Error {
+        ParseError::new(DSError::InvalidName(reason), offset).into_error()
+    }
+
     /// Uncompresses a name starting at `offset`, and puts the result into
     /// `name`. This function assumes that the input is trusted and doesn't
     /// perform any checks. Returns the length of the name as well as the
@@ -268,6 +310,20 @@ impl Compress {
     pub fn uncompress_with_previous_offset(
         packet: &[u8],
         ref_offset: usize,
+    ) -> Result<(Vec<u8>, usize), Error> {
+        Self::uncompress_with_previous_offset_and_options(
+            packet,
+            ref_offset,
+            ParseOptions::default(),
+        )
+    }
+
+    /// Uncompresses a packet that was parsed using `parse_options`, and
+    /// returns the new location of the record that was at `ref_offset`.
+    pub fn uncompress_with_previous_offset_and_options(
+        packet: &[u8],
+        ref_offset: usize,
+        parse_options: ParseOptions,
     ) -> Result<(Vec<u8>, usize), Error> {
         let packet = packet.to_owned();
         if packet.len() < DNS_HEADER_SIZE {
@@ -276,7 +332,7 @@ impl Compress {
         let mut new_offset = None;
         let mut uncompressed = Vec::new();
         uncompressed.extend_from_slice(&packet[..DNS_HEADER_SIZE]);
-        let mut parsed_packet = DNSSector::new(packet)?.parse()?;
+        let mut parsed_packet = DNSSector::new(packet)?.parse_with(parse_options)?;
         {
             let mut it = parsed_packet.into_iter_question();
             while let Some(item) = it {
@@ -346,17 +402,34 @@ impl Compress {
     }
 
     pub fn uncompress(packet: &[u8]) -> Result<Vec<u8>, Error> {
-        Self::uncompress_with_previous_offset(packet, DNS_HEADER_SIZE).map(|x| x.0)
+        Self::uncompress_with_options(packet, ParseOptions::default())
+    }
+
+    /// Uncompresses a packet that was parsed using `parse_options`.
+    pub fn uncompress_with_options(
+        packet: &[u8],
+        parse_options: ParseOptions,
+    ) -> Result<Vec<u8>, Error> {
+        Self::uncompress_with_previous_offset_and_options(packet, DNS_HEADER_SIZE, parse_options)
+            .map(|x| x.0)
     }
 
     pub fn compress(packet: &[u8]) -> Result<Vec<u8>, Error> {
+        Self::compress_with_options(packet, ParseOptions::default())
+    }
+
+    /// Compresses a packet that was parsed using `parse_options`.
+    pub fn compress_with_options(
+        packet: &[u8],
+        parse_options: ParseOptions,
+    ) -> Result<Vec<u8>, Error> {
         let packet = packet.to_owned();
         if packet.len() < DNS_HEADER_SIZE {
             bail!(DSError::PacketTooSmall);
         }
         let mut compressed = Vec::new();
         compressed.extend_from_slice(&packet[..DNS_HEADER_SIZE]);
-        let mut parsed_packet = DNSSector::new(packet)?.parse()?;
+        let mut parsed_packet = DNSSector::new(packet)?.parse_with(parse_options)?;
         let mut dict = SuffixDict::new();
         {
             let mut it = parsed_packet.into_iter_question();
@@ -488,17 +561,15 @@ impl Compress {
 
     /// Convert a trusted raw name to a string
     pub fn raw_name_to_str(packet: &[u8], mut offset: usize) -> Vec<u8> {
-        let mut indirections = 0;
         let mut res: Vec<u8> = Vec::with_capacity(64);
         loop {
             let label_len = match packet[offset] {
                 0 => break,
                 len if len & 0xc0 == 0xc0 => {
                     let new_offset = (BigEndian::read_u16(&packet[offset..]) & 0x3fff) as usize;
-                    if new_offset == offset || indirections > DNS_MAX_HOSTNAME_INDIRECTIONS {
+                    if new_offset >= offset {
                         return res;
                     }
-                    indirections += 1;
                     offset = new_offset;
                     continue;
                 }
@@ -565,13 +636,24 @@ impl Compress {
     /// This function assumes that the input is trusted and uncompressed, and
     /// doesn't perform any checks. Returns the length of the name as well
     /// as the location right after the uncompressed name.
+    ///
+    /// Suffixes are stored with their location in `compressed`, which may
+    /// differ from their location in `packet`.
     pub fn copy_compressed_name(
         dict: &mut SuffixDict,
         compressed: &mut Vec<u8>,
         packet: &[u8],
         offset: usize,
     ) -> CompressedNameResult {
-        Self::copy_compressed_name_with_base_offset(dict, compressed, packet, offset, 0)
+        let name_len = Compress::raw_name_len(&packet[offset..]);
+        let name = &packet[offset..offset + name_len];
+        let base_offset = compressed.len();
+        let compressed_name =
+            Self::copy_compressed_name_with_base_offset(dict, compressed, name, 0, base_offset);
+        CompressedNameResult {
+            name_len: compressed_name.name_len,
+            final_offset: offset + name_len,
+        }
     }
 }
 
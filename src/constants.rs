@@ -36,6 +36,9 @@ pub const DNS_RR_TTL_OFFSET: usize = 4;
 /// Offset to the rdata length, from the location right after the RR name.
 pub const DNS_RR_RDLEN_OFFSET: usize = 8;
 
+/// Size of the fixed part of a SOA record, after the names.
+pub const DNS_SOA_RR_FIXED_SIZE: usize = 20;
+
 /// Offset to the maximum payload for edns0-enabled UDP packets, for OPT RRs.
 pub const DNS_OPT_RR_MAX_PAYLOAD_OFFSET: usize = 2;
 
@@ -74,12 +77,29 @@ pub const DNS_FLAGS_OFFSET: usize = 2;
 /// Offset to the return code, from the beginning of the DNS packet
 pub const DNS_RCODE_OFFSET: usize = 3;
 
+/// Offset to the number of records in the question section, from the
+/// beginning of the DNS packet
+pub const DNS_QDCOUNT_OFFSET: usize = 4;
+
+/// Offset to the number of records in the answer section, from the beginning
+/// of the DNS packet
+pub const DNS_ANCOUNT_OFFSET: usize = 6;
+
+/// Offset to the number of records in the name servers section, from the
+/// beginning of the DNS packet
+pub const DNS_NSCOUNT_OFFSET: usize = 8;
+
+/// Offset to the number of records in the additional section, from the
+/// beginning of the DNS packet
+pub const DNS_ARCOUNT_OFFSET: usize = 10;
+
 // DNS flags - 32 bit because we include extended flags
 pub const DNS_FLAG_QR: u32 = 1 << 15;
 pub const DNS_FLAG_AA: u32 = 1 << 10;
 pub const DNS_FLAG_TC: u32 = 1 << 9;
 pub const DNS_FLAG_RD: u32 = 1 << 8;
 pub const DNS_FLAG_RA: u32 = 1 << 7;
+pub const DNS_FLAG_Z: u32 = 1 << 6;
 pub const DNS_FLAG_AD: u32 = 1 << 5;
 pub const DNS_FLAG_CD: u32 = 1 << 4;
 pub const DNS_FLAG_DO: u32 = 1 << 31;
@@ -90,6 +110,13 @@ pub const DNS_MAX_UNCOMPRESSED_SIZE: usize = 8192;
 /// Maximum size of a compressed packet
 pub const DNS_MAX_COMPRESSED_SIZE: usize = 4096;
 
+/// Maximum size of a packet sent over TCP
+pub const DNS_MAX_PACKET_SIZE: usize = 0xffff;
+
+/// UDP payload size advertised by default in responses, that avoids IP
+/// fragmentation on most networks
+pub const DNS_DEFAULT_EDNS_PAYLOAD_SIZE: u16 = 1232;
+
 /// DNS query class
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Class {
@@ -326,6 +353,7 @@ pub enum EdnsOption {
     CLIENT_SUBNET = 8,
     KEEPALIVE = 11,
     PADDING = 12,
+    EXTENDED_ERROR = 15,
 }
 
 impl From<EdnsOption> for u16 {
@@ -334,41 +362,215 @@ impl From<EdnsOption> for u16 {
     }
 }
 
-/// DNS return codes
+/// Extended DNS Error codes (RFC 8914)
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExtendedError {
+    OTHER = 0,
+    UNSUPPORTED_DNSKEY_ALGORITHM = 1,
+    UNSUPPORTED_DS_DIGEST_TYPE = 2,
+    STALE_ANSWER = 3,
+    FORGED_ANSWER = 4,
+    DNSSEC_INDETERMINATE = 5,
+    DNSSEC_BOGUS = 6,
+    SIGNATURE_EXPIRED = 7,
+    SIGNATURE_NOT_YET_VALID = 8,
+    DNSKEY_MISSING = 9,
+    RRSIGS_MISSING = 10,
+    NO_ZONE_KEY_BIT_SET = 11,
+    NSEC_MISSING = 12,
+    CACHED_ERROR = 13,
+    NOT_READY = 14,
+    BLOCKED = 15,
+    CENSORED = 16,
+    FILTERED = 17,
+    PROHIBITED = 18,
+    STALE_NXDOMAIN_ANSWER = 19,
+    NOT_AUTHORITATIVE = 20,
+    NOT_SUPPORTED = 21,
+    NO_REACHABLE_AUTHORITY = 22,
+    NETWORK_ERROR = 23,
+    INVALID_DATA = 24,
+}
+
+impl From<ExtendedError> for u16 {
+    fn from(v: ExtendedError) -> u16 {
+        v as u16
+    }
+}
+
+/// DNS return codes, including extended return codes that require an `OPT`
+/// record (RFC 6891 section 6.1.3)
+///
+/// Since `Unknown` carries a value, variants can't be converted with
+/// `as u8` any more: use `u16::from()` for the full code, or `u8::from()`
+/// for the 4 bits stored in the header.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Rcode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMPL = 4,
-    REFUSED = 5,
-    YXDOMAIN = 6,
-    YXRRSET = 7,
-    NXRRSET = 8,
-    NOTAUTH = 9,
-    NOTZONE = 10,
+    NOERROR,
+    FORMERR,
+    SERVFAIL,
+    NXDOMAIN,
+    NOTIMPL,
+    REFUSED,
+    YXDOMAIN,
+    YXRRSET,
+    NXRRSET,
+    NOTAUTH,
+    NOTZONE,
+    BADVERS,
+    BADKEY,
+    BADTIME,
+    BADMODE,
+    BADNAME,
+    BADALG,
+    BADTRUNC,
+    BADCOOKIE,
+    Unknown(u16),
 }
 
+impl From<u16> for Rcode {
+    fn from(v: u16) -> Rcode {
+        match v {
+            0 => Rcode::NOERROR,
+            1 => Rcode::FORMERR,
+            2 => Rcode::SERVFAIL,
+            3 => Rcode::NXDOMAIN,
+            4 => Rcode::NOTIMPL,
+            5 => Rcode::REFUSED,
+            6 => Rcode::YXDOMAIN,
+            7 => Rcode::YXRRSET,
+            8 => Rcode::NXRRSET,
+            9 => Rcode::NOTAUTH,
+            10 => Rcode::NOTZONE,
+            16 => Rcode::BADVERS,
+            17 => Rcode::BADKEY,
+            18 => Rcode::BADTIME,
+            19 => Rcode::BADMODE,
+            20 => Rcode::BADNAME,
+            21 => Rcode::BADALG,
+            22 => Rcode::BADTRUNC,
+            23 => Rcode::BADCOOKIE,
+            v => Rcode::Unknown(v),
+        }
+    }
+}
+
+impl From<Rcode> for u16 {
+    fn from(v: Rcode) -> u16 {
+        match v {
+            Rcode::NOERROR => 0,
+            Rcode::FORMERR => 1,
+            Rcode::SERVFAIL => 2,
+            Rcode::NXDOMAIN => 3,
+            Rcode::NOTIMPL => 4,
+            Rcode::REFUSED => 5,
+            Rcode::YXDOMAIN => 6,
+            Rcode::YXRRSET => 7,
+            Rcode::NXRRSET => 8,
+            Rcode::NOTAUTH => 9,
+            Rcode::NOTZONE => 10,
+            Rcode::BADVERS => 16,
+            Rcode::BADKEY => 17,
+            Rcode::BADTIME => 18,
+            Rcode::BADMODE => 19,
+            Rcode::BADNAME => 20,
+            Rcode::BADALG => 21,
+            Rcode::BADTRUNC => 22,
+            Rcode::BADCOOKIE => 23,
+            Rcode::Unknown(v) => v,
+        }
+    }
+}
+
+/// Returns the lower 4 bits of the code, that are stored in the header. The
+/// upper 8 bits of extended codes are stored in the `OPT` record.
 impl From<Rcode> for u8 {
     fn from(v: Rcode) -> u8 {
-        v as u8
+        (u16::from(v) & 0x0f) as u8
     }
 }
 
 /// DNS opcodes
+///
+/// Opcodes are converted with `u8::from()`. The `as` cast no longer works,
+/// now that unassigned values are kept in `Unknown`.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Opcode {
-    QUERY = 0,
-    IQUERY = 1,
-    STATUS = 2,
-    NOTIFY = 4,
-    UPDATE = 5,
+    QUERY,
+    IQUERY,
+    STATUS,
+    NOTIFY,
+    UPDATE,
+    DSO, // RFC 8490
+    Unknown(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(v: u8) -> Opcode {
+        match v {
+            0 => Opcode::QUERY,
+            1 => Opcode::IQUERY,
+            2 => Opcode::STATUS,
+            4 => Opcode::NOTIFY,
+            5 => Opcode::UPDATE,
+            6 => Opcode::DSO,
+            v => Opcode::Unknown(v),
+        }
+    }
 }
 
 impl From<Opcode> for u8 {
     fn from(v: Opcode) -> u8 {
-        v as u8
+        match v {
+            Opcode::QUERY => 0,
+            Opcode::IQUERY => 1,
+            Opcode::STATUS => 2,
+            Opcode::NOTIFY => 4,
+            Opcode::UPDATE => 5,
+            Opcode::DSO => 6,
+            Opcode::Unknown(v) => v,
+        }
+    }
+}
+
+/// Individual header flags, and the `DO` flag from the extended flags of the
+/// `OPT` record
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Flag {
+    QR,
+    AA,
+    TC,
+    RD,
+    RA,
+    Z,
+    AD,
+    CD,
+    DO,
+}
+
+impl Flag {
+    /// Returns the mask of the flag, in the 32-bit representation returned by
+    /// `ParsedPacket::flags()`.
+    pub fn mask(self) -> u32 {
+        match self {
+            Flag::QR => DNS_FLAG_QR,
+            Flag::AA => DNS_FLAG_AA,
+            Flag::TC => DNS_FLAG_TC,
+            Flag::RD => DNS_FLAG_RD,
+            Flag::RA => DNS_FLAG_RA,
+            Flag::Z => DNS_FLAG_Z,
+            Flag::AD => DNS_FLAG_AD,
+            Flag::CD => DNS_FLAG_CD,
+            Flag::DO => DNS_FLAG_DO,
+        }
+    }
+
+    /// Returns `true` if the flag is stored in the `OPT` record rather than in
+    /// the header.
+    #[inline]
+    pub fn is_extended(self) -> bool {
+        self.mask() > 0xffff
     }
 }
 
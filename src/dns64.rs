@@ -0,0 +1,263 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::compress::*;
+use crate::constants::*;
+use crate::errors::*;
+use crate::ip_prefix::*;
+use crate::parsed_packet::*;
+use crate::response_options::*;
+use crate::rr_iterator::*;
+use crate::synth::r#gen;
+
+/// The Well-Known Prefix for IPv4-embedded IPv6 addresses (RFC 6052 section
+/// 2.1), `64:ff9b::/96`.
+pub const DNS64_WELL_KNOWN_PREFIX: IpPrefix = IpPrefix::new_unchecked(
+    IpAddr::V6(Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0)),
+    96,
+);
+
+/// Default TTL of the `CNAME` records returned for reverse queries.
+pub const DNS64_DEFAULT_PTR_TTL: u32 = 300;
+
+/// A `Dns64` synthesizes `AAAA` records from `A` records, for IPv6-only
+/// clients reaching IPv4 servers through a NAT64 gateway (RFC 6147).
+///
+/// A typical resolver forwards a `AAAA` query, and if `needs_synthesis()`
+/// returns `Ok(true)` for the response, sends an `A` query for the same name and
+/// builds the response to the client with `synthesize()`. Reverse queries
+/// for synthesized addresses are answered by `synthesize_ptr()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Dns64 {
+    prefix: IpPrefix,
+    prefix_octets: [u8; 16],
+    /// `A` records that must not be used to synthesize `AAAA` records.
+    pub excluded_ipv4: Vec<IpPrefix>,
+    /// `AAAA` records that are ignored, as if the response didn't include
+    /// them (RFC 6147 section 5.1.4).
+    pub excluded_ipv6: Vec<IpPrefix>,
+    /// TTL of the `CNAME` records returned for reverse queries.
+    pub ptr_ttl: u32,
+}
+
+impl Dns64 {
+    /// Creates a `Dns64` using `prefix`, whose length must be 32, 40, 48,
+    /// 56, 64 or 96 bits. IPv4-mapped IPv6 addresses (`::ffff:0:0/96`) are
+    /// excluded.
+    pub fn new(prefix: IpPrefix) -> Result<Self, Error> {
+        let prefix_octets = match prefix.addr() {
+            IpAddr::V4(_) => bail!(DSError::WrongAddressFamily),
+            IpAddr::V6(addr) => addr.octets(),
+        };
+        if ![32, 40, 48, 56, 64, 96].contains(&prefix.prefix_len()) {
+            bail!(DSError::InvalidPrefix(
+                "DNS64 prefixes must be /32, /40, /48, /56, /64 or /96"
+            ));
+        }
+        if prefix_octets[8] != 0 {
+            bail!(DSError::InvalidPrefix("Bits 64 to 71 must be zero"));
+        }
+        let ipv4_mapped =
+            IpPrefix::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0)), 96)?;
+        Ok(Dns64 {
+            prefix,
+            prefix_octets,
+            excluded_ipv4: vec![],
+            excluded_ipv6: vec![ipv4_mapped],
+            ptr_ttl: DNS64_DEFAULT_PTR_TTL,
+        })
+    }
+
+    /// Creates a `Dns64` using the Well-Known Prefix, that must not be used
+    /// with non-global IPv4 addresses (RFC 6052 section 3.1): these, as well
+    /// as special-use addresses (RFC 6890), are excluded.
+    pub fn well_known() -> Self {
+        let mut dns64 = Dns64::new(DNS64_WELL_KNOWN_PREFIX).unwrap();
+        dns64.excluded_ipv4 = SPECIAL_USE_IPV4_PREFIXES
+            .iter()
+            .map(|prefix| prefix.parse().unwrap())
+            .collect();
+        dns64
+    }
+
+    /// Excludes the `A` records from `prefix`.
+    pub fn with_excluded_ipv4(mut self, prefix: IpPrefix) -> Self {
+        self.excluded_ipv4.push(prefix);
+        self
+    }
+
+    /// Ignores the `AAAA` records from `prefix`.
+    pub fn with_excluded_ipv6(mut self, prefix: IpPrefix) -> Self {
+        self.excluded_ipv6.push(prefix);
+        self
+    }
+
+    /// Sets the TTL of the `CNAME` records returned for reverse queries.
+    pub fn with_ptr_ttl(mut self, ptr_ttl: u32) -> Self {
+        self.ptr_ttl = ptr_ttl;
+        self
+    }
+
+    /// Returns the prefix IPv4 addresses are embedded into (RFC 6052
+    /// section 2.2).
+    #[inline]
+    pub fn prefix(&self) -> IpPrefix {
+        self.prefix
+    }
+
+    /// Embeds an IPv4 address into the prefix (RFC 6052 section 2.2).
+    pub fn synthesize_address(&self, ipv4: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix_octets;
+        for (i, octet) in self.ipv4_positions().zip(ipv4.octets()) {
+            octets[i] = octet;
+        }
+        Ipv6Addr::from(octets)
+    }
+
+    /// Extracts the IPv4 address embedded into `ipv6`, if it belongs to the
+    /// prefix.
+    pub fn extract_ipv4(&self, ipv6: &Ipv6Addr) -> Option<Ipv4Addr> {
+        if !self.prefix.contains(&IpAddr::V6(*ipv6)) {
+            return None;
+        }
+        let octets = ipv6.octets();
+        let mut ipv4 = [0u8; 4];
+        for (octet, i) in ipv4.iter_mut().zip(self.ipv4_positions()) {
+            *octet = octets[i];
+        }
+        Some(Ipv4Addr::from(ipv4))
+    }
+
+    /// Returns `true` if `aaaa_response`, a response to a `AAAA` query,
+    /// doesn't include any usable `AAAA` record, so that an `A` query has to
+    /// be sent in order to synthesize them (RFC 6147 section 5.1).
+    ///
+    /// `NXDOMAIN` responses are returned as-is. Other errors are handled like
+    /// empty responses. An error is returned if a lazily parsed response
+    /// turns out to be invalid.
+    pub fn needs_synthesis(&self, aaaa_response: &mut ParsedPacket) -> Result<bool, Error> {
+        match aaaa_response.typed_rcode() {
+            Rcode::NXDOMAIN => Ok(false),
+            Rcode::NOERROR => {
+                let mut it = aaaa_response.try_into_iter_answer()?;
+                while let Some(item) = it {
+                    if item.rr_type() == Type::AAAA.into() {
+                        if let Ok(ip) = item.rr_ip() {
+                            if !self.excluded_ipv6.iter().any(|prefix| prefix.contains(&ip)) {
+                                return Ok(false);
+                            }
+                        }
+                    }
+                    it = item.next();
+                }
+                Ok(true)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Builds a response to the `AAAA` query `query` from `a_response`, the
+    /// response to an `A` query for the same name.
+    ///
+    /// `CNAME` records are copied, and a `AAAA` record is synthesized for
+    /// every `A` record that isn't excluded, with the same name and TTL.
+    /// The return code and the `RA` flag are copied from `a_response`.
+    pub fn synthesize(
+        &self,
+        query: &ParsedPacket,
+        a_response: &mut ParsedPacket,
+    ) -> Result<ParsedPacket, Error> {
+        if query.qtype_qclass() != Some((Type::AAAA.into(), Class::IN.into())) {
+            bail!(DSError::InvalidPacket("Not a AAAA query"));
+        }
+        let options = ResponseOptions::default()
+            .with_rcode(a_response.typed_rcode())
+            .with_recursion_available(a_response.ra());
+        let mut response = query.to_response(options)?;
+        let mut rrs = vec![];
+        let mut it = a_response.try_into_iter_answer()?;
+        while let Some(item) = it {
+            if item.rr_class() == Class::IN.into() {
+                let raw = item.raw();
+                let rr_header = |rr_type| r#gen::RRHeader {
+                    name: Compress::raw_name_to_str(raw.packet, raw.offset),
+                    ttl: item.rr_ttl(),
+                    class: Class::IN,
+                    rr_type,
+                };
+                match item.rr_type() {
+                    x if x == Type::CNAME.into() => {
+                        let mut target = Vec::with_capacity(DNS_MAX_HOSTNAME_LEN);
+                        Compress::copy_uncompressed_name(
+                            &mut target,
+                            raw.packet,
+                            raw.name_end + DNS_RR_HEADER_SIZE,
+                        );
+                        rrs.push(r#gen::RR::new(rr_header(Type::CNAME), &target)?);
+                    }
+                    x if x == Type::A.into() => {
+                        if let Ok(IpAddr::V4(ipv4)) = item.rr_ip() {
+                            let ip = IpAddr::V4(ipv4);
+                            if !self.excluded_ipv4.iter().any(|prefix| prefix.contains(&ip)) {
+                                let ipv6 = self.synthesize_address(ipv4);
+                                rrs.push(r#gen::AAAA::build(rr_header(Type::AAAA), ipv6)?);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            it = item.next();
+        }
+        for rr in rrs {
+            response.insert_rr(Section::Answer, rr)?;
+        }
+        Ok(response)
+    }
+
+    /// Returns the `in-addr.arpa` name of the IPv4 address embedded into
+    /// `name`, if `name` is the `ip6.arpa` name of an address from the
+    /// prefix. Names are dot-delimited, without a trailing dot.
+    pub fn ptr_target(&self, name: &[u8]) -> Option<Vec<u8>> {
+        match reverse_name_to_ip(name)? {
+            IpAddr::V6(ipv6) => Some(reverse_name(&IpAddr::V4(self.extract_ipv4(&ipv6)?))),
+            IpAddr::V4(_) => None,
+        }
+    }
+
+    /// Answers a `PTR` query for the `ip6.arpa` name of a synthesized
+    /// address with a `CNAME` record to the `in-addr.arpa` name of the IPv4
+    /// address (RFC 6147 section 5.3.1), that the resolver can then follow.
+    ///
+    /// Returns `None` if this is not such a query.
+    pub fn synthesize_ptr(&self, query: &mut ParsedPacket) -> Result<Option<ParsedPacket>, Error> {
+        let (name, rr_type, rr_class) = match query.question() {
+            None => return Ok(None),
+            Some(question) => question,
+        };
+        if rr_type != Type::PTR.into() || rr_class != Class::IN.into() {
+            return Ok(None);
+        }
+        let target = match self.ptr_target(&name) {
+            None => return Ok(None),
+            Some(target) => r#gen::raw_name_from_str(&target, None)?,
+        };
+        let options = ResponseOptions::default().with_recursion_available(true);
+        let mut response = query.to_response(options)?;
+        let rr_header = r#gen::RRHeader {
+            name,
+            ttl: self.ptr_ttl,
+            class: Class::IN,
+            rr_type: Type::CNAME,
+        };
+        response.insert_rr(Section::Answer, r#gen::RR::new(rr_header, &target)?)?;
+        Ok(Some(response))
+    }
+
+    /// Returns the locations of the bytes of the embedded IPv4 address,
+    /// skipping the reserved bits 64 to 71.
+    fn ipv4_positions(&self) -> impl Iterator<Item = usize> {
+        (self.prefix.prefix_len() as usize / 8..16)
+            .filter(|&i| i != 8)
+            .take(4)
+    }
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::mem;
 
 use byteorder::{BigEndian, ByteOrder};
@@ -5,15 +6,23 @@ use byteorder::{BigEndian, ByteOrder};
 use crate::compress::*;
 use crate::constants::*;
 use crate::errors::*;
+use crate::parse_options::*;
 use crate::parsed_packet::*;
+use crate::parsed_packet_ref::*;
+use crate::rdata_validator::*;
 
 /// A `DNSSector` object summarizes the structure of a DNS packet,
 /// so that individual sections can be accessed quickly.
 ///
 /// The functions implemented here assume an untrusted input packet.
+///
+/// The packet can either be owned (`DNSSector<Vec<u8>>`, the default), in
+/// which case parsing returns a mutable `ParsedPacket`, or borrowed
+/// (`DNSSector<&[u8]>`), in which case parsing returns a read-only
+/// `ParsedPacketRef` without copying the packet.
 #[derive(Clone, Debug)]
-pub struct DNSSector {
-    pub packet: Vec<u8>,
+pub struct DNSSector<P = Vec<u8>> {
+    pub packet: P,
     pub offset: usize,
     pub edns_start: Option<usize>,
     pub edns_end: Option<usize>,
@@ -22,14 +31,10 @@ pub struct DNSSector {
     pub edns_version: Option<u8>,
     pub ext_flags: Option<u16>,
     pub max_payload: usize,
+    pub parse_options: ParseOptions,
 }
 
 impl DNSSector {
-    /// Consumes the parser and return the original packet
-    pub fn into_packet(self) -> Vec<u8> {
-        self.packet
-    }
-
     /// Check if this is a response
     #[inline]
     pub fn is_response(packet: &[u8]) -> bool {
@@ -37,6 +42,13 @@ impl DNSSector {
             == DNS_FLAG_QR as u16
     }
 
+    /// Check if the truncation bit is set
+    #[inline]
+    pub fn is_truncated(packet: &[u8]) -> bool {
+        BigEndian::read_u16(&packet[DNS_FLAGS_OFFSET..]) & (DNS_FLAG_TC as u16)
+            == DNS_FLAG_TC as u16
+    }
+
     /// Set the response bit
     #[inline]
     pub fn set_response(packet: &mut [u8], is_response: bool) {
@@ -52,59 +64,330 @@ impl DNSSector {
     /// Returns the number of records in the question section.
     #[inline]
     pub fn qdcount(packet: &[u8]) -> u16 {
-        BigEndian::read_u16(&packet[4..])
+        BigEndian::read_u16(&packet[DNS_QDCOUNT_OFFSET..])
     }
 
     /// Changes the number of questions.
     #[allow(dead_code)]
     #[inline]
     pub fn set_qdcount(packet: &mut [u8], value: u16) {
-        BigEndian::write_u16(&mut packet[4..], value);
+        BigEndian::write_u16(&mut packet[DNS_QDCOUNT_OFFSET..], value);
     }
 
     /// Returns the numbersof records in the answer section.
     #[inline]
     pub fn ancount(packet: &[u8]) -> u16 {
-        BigEndian::read_u16(&packet[6..])
+        BigEndian::read_u16(&packet[DNS_ANCOUNT_OFFSET..])
     }
 
     /// Changes the number of records in the answer section.
     #[allow(dead_code)]
     #[inline]
     pub fn set_ancount(packet: &mut [u8], value: u16) {
-        BigEndian::write_u16(&mut packet[6..], value)
+        BigEndian::write_u16(&mut packet[DNS_ANCOUNT_OFFSET..], value)
     }
 
     /// Returns the number of records in the nameservers section.
     #[inline]
     pub fn nscount(packet: &[u8]) -> u16 {
-        BigEndian::read_u16(&packet[8..])
+        BigEndian::read_u16(&packet[DNS_NSCOUNT_OFFSET..])
     }
 
     /// Changes the number of records in the nameservers section.
     #[allow(dead_code)]
     #[inline]
     pub fn set_nscount(packet: &mut [u8], value: u16) {
-        BigEndian::write_u16(&mut packet[8..], value)
+        BigEndian::write_u16(&mut packet[DNS_NSCOUNT_OFFSET..], value)
     }
 
     /// Returns the number of records in the additional section.
     #[inline]
     pub fn arcount(packet: &[u8]) -> u16 {
-        BigEndian::read_u16(&packet[10..])
+        BigEndian::read_u16(&packet[DNS_ARCOUNT_OFFSET..])
     }
 
     /// Changes the number of records in the additional section.
     #[allow(dead_code)]
     #[inline]
     pub fn set_arcount(packet: &mut [u8], value: u16) {
-        BigEndian::write_u16(&mut packet[10..], value)
+        BigEndian::write_u16(&mut packet[DNS_ARCOUNT_OFFSET..], value)
+    }
+
+    /// Removes the records that `dropped_records` doesn't keep from a
+    /// truncated response: section counts are adjusted, and the packet is cut
+    /// right before the first dropped record, at `offset`.
+    fn drop_records(packet: &mut Vec<u8>, offset: usize, dropped_records: DroppedRecords) {
+        let ancount = Self::ancount(packet) - dropped_records.answers;
+        let nscount = Self::nscount(packet) - dropped_records.nameservers;
+        let arcount = Self::arcount(packet) - dropped_records.additional;
+        Self::set_ancount(packet, ancount);
+        Self::set_nscount(packet, nscount);
+        Self::set_arcount(packet, arcount);
+        packet.truncate(offset);
+    }
+
+    /// Parses and validates all records from all sections of an untrusted DNS
+    /// packet. If the validation succeeds, a `ParsedPacket` structure
+    /// containing information to quickly access (extended) flags and
+    /// individual sections is returned.
+    pub fn parse(self) -> Result<ParsedPacket, Error> {
+        self.parse_with(ParseOptions::default())
+    }
+
+    /// Parses and validates an untrusted DNS packet like `parse()` does, using
+    /// `parse_options` to decide what the question section may contain and
+    /// whether queries may include records.
+    pub fn parse_with(self, parse_options: ParseOptions) -> Result<ParsedPacket, Error> {
+        self.try_parse_with(parse_options)
+            .map_err(ParseError::into_error)
+    }
+
+    /// Parses and validates an untrusted DNS packet like `parse_with()` does,
+    /// but returns a `ParseError` describing where the packet was rejected
+    /// instead of a generic `Error`.
+    pub fn try_parse_with(
+        mut self,
+        parse_options: ParseOptions,
+    ) -> Result<ParsedPacket, ParseError> {
+        let sections = self.parse_sections(parse_options)?;
+        if let Some(dropped_records) = sections.dropped_records {
+            Self::drop_records(&mut self.packet, self.offset, dropped_records);
+        }
+        Ok(self.into_parsed_packet(sections))
+    }
+
+    /// Parses and validates the header, the question section and the `OPT`
+    /// record of an untrusted DNS packet, using the default parsing options.
+    /// See `parse_lazy_with()`.
+    pub fn parse_lazy(self) -> Result<ParsedPacket, Error> {
+        self.parse_lazy_with(ParseOptions::default())
+    }
+
+    /// Parses and validates the header, the question section and the `OPT`
+    /// record of an untrusted DNS packet. Other records are only skipped
+    /// over in order to find the `OPT` record.
+    ///
+    /// Validation of the answer, name servers and additional sections is
+    /// deferred until a section iterator or a function modifying the packet
+    /// is used, or until `ParsedPacket::validate()` is called.
+    pub fn parse_lazy_with(self, parse_options: ParseOptions) -> Result<ParsedPacket, Error> {
+        self.try_parse_lazy_with(parse_options)
+            .map_err(ParseError::into_error)
+    }
+
+    /// Lazily parses an untrusted DNS packet like `parse_lazy_with()` does,
+    /// but returns a `ParseError` describing where the packet was rejected.
+    pub fn try_parse_lazy_with(
+        mut self,
+        parse_options: ParseOptions,
+    ) -> Result<ParsedPacket, ParseError> {
+        let offset_question = self.parse_header_and_question(parse_options)?;
+        if self.find_opt().is_err() {
+            // Let a complete validation report the error, or recover the
+            // packet if it was truncated.
+            self.offset = 0;
+            self.reset_edns();
+            return self.try_parse_with(parse_options);
+        }
+        let sections = Sections {
+            offset_question,
+            offset_answers: None,
+            offset_nameservers: None,
+            offset_additional: None,
+            dropped_records: None,
+        };
+        let mut parsed_packet = self.into_parsed_packet(sections);
+        parsed_packet.pending_validation = true;
+        Ok(parsed_packet)
+    }
+
+    /// Builds a `ParsedPacket` from the location of its sections.
+    fn into_parsed_packet(self, sections: Sections) -> ParsedPacket {
+        ParsedPacket {
+            packet: Some(self.packet),
+            offset_question: sections.offset_question,
+            offset_answers: sections.offset_answers,
+            offset_nameservers: sections.offset_nameservers,
+            offset_additional: sections.offset_additional,
+            offset_edns: self.edns_start,
+            ext_rcode: self.ext_rcode,
+            edns_version: self.edns_version,
+            ext_flags: self.ext_flags,
+            edns_count: self.edns_count,
+            maybe_compressed: true,
+            max_payload: self.max_payload,
+            cached: None,
+            parse_options: self.parse_options,
+            dropped_records: sections.dropped_records,
+            pending_validation: false,
+        }
+    }
+
+    /// Checks that an untrusted encoded DNS name is valid and does not contain
+    /// any indirections. Returns the location right after the name.
+    ///
+    /// Errors are `ParseError`s carrying the offset of the offending label.
+    pub fn check_uncompressed_name(packet: &[u8], mut offset: usize) -> Result<usize, Error> {
+        let packet_len = packet.len();
+        let mut name_len = 0;
+        if offset >= packet_len {
+            bail!(ParseError::new(
+                DSError::InternalError("Offset outside packet boundaries"),
+                offset
+            )
+            .into_error());
+        }
+        if 1 > packet_len - offset {
+            bail!(Self::invalid_name("Empty name", offset))
+        }
+        loop {
+            if offset >= packet_len {
+                bail!(Self::invalid_name("Truncated name", offset))
+            }
+            let label_len = match packet[offset] {
+                len if len & 0xc0 == 0xc0 => {
+                    bail!(Self::invalid_name("Unexpected compression", offset))
+                }
+                len if len > 0x3f => {
+                    bail!(Self::invalid_name("Label length too long", offset))
+                }
+                len => len as usize,
+            };
+            if label_len >= packet_len - offset {
+                bail!(Self::invalid_name("Out-of-bounds name", offset))
+            }
+            name_len += label_len + 1;
+            if name_len > DNS_MAX_HOSTNAME_LEN {
+                bail!(Self::invalid_name("Name too long", offset))
+            }
+            offset += label_len + 1;
+            if label_len == 0 {
+                break;
+            }
+        }
+        Ok(offset)
+    }
+
+    /// Builds the error returned by `check_uncompressed_name()` for a name
+    /// rejected at `offset`.
+    #[inline]
+    fn invalid_name(reason: &'static str, offset: usize) -> Error {
+        Self::error_at(DSError::InvalidName(reason), offset)
+    }
+
+    /// Builds an error raised by the field starting at `offset`.
+    #[inline]
+    fn error_at(kind: DSError, offset: usize) -> Error {
+        ParseError::new(kind, offset).into_error()
+    }
+
+    /// Attributes an error to the field starting at `offset`, unless it
+    /// already carries a location.
+    fn locate(error: Error, offset: usize) -> Error {
+        if ParseError::from_error(&error).is_some() {
+            return error;
+        }
+        let kind = error
+            .downcast::<DSError>()
+            .unwrap_or(DSError::InternalError("Unexpected error type"));
+        Self::error_at(kind, offset)
+    }
+}
+
+impl<'a> DNSSector<&'a [u8]> {
+    /// Parses and validates all records from all sections of an untrusted,
+    /// borrowed DNS packet, with the same rules as `DNSSector::parse()`. The
+    /// returned `ParsedPacketRef` doesn't copy the packet.
+    pub fn parse(self) -> Result<ParsedPacketRef<'a>, Error> {
+        self.parse_with(ParseOptions::default())
+    }
+
+    /// Parses and validates a borrowed DNS packet like `parse()` does, using
+    /// `parse_options`.
+    pub fn parse_with(self, parse_options: ParseOptions) -> Result<ParsedPacketRef<'a>, Error> {
+        self.try_parse_with(parse_options)
+            .map_err(ParseError::into_error)
+    }
+
+    /// Parses and validates a borrowed DNS packet like `parse_with()` does,
+    /// but returns a `ParseError` describing where the packet was rejected.
+    ///
+    /// The packet is only copied if records had to be dropped from a
+    /// truncated response.
+    pub fn try_parse_with(
+        mut self,
+        parse_options: ParseOptions,
+    ) -> Result<ParsedPacketRef<'a>, ParseError> {
+        let sections = self.parse_sections(parse_options)?;
+        let packet = match sections.dropped_records {
+            None => Cow::Borrowed(self.packet),
+            Some(dropped_records) => {
+                let mut packet = self.packet.to_vec();
+                DNSSector::drop_records(&mut packet, self.offset, dropped_records);
+                Cow::Owned(packet)
+            }
+        };
+        let parsed_packet_ref = ParsedPacketRef {
+            packet,
+            offset_question: sections.offset_question,
+            offset_answers: sections.offset_answers,
+            offset_nameservers: sections.offset_nameservers,
+            offset_additional: sections.offset_additional,
+            offset_edns: self.edns_start,
+            ext_rcode: self.ext_rcode,
+            edns_version: self.edns_version,
+            ext_flags: self.ext_flags,
+            edns_count: self.edns_count,
+            max_payload: self.max_payload,
+            parse_options: self.parse_options,
+            dropped_records: sections.dropped_records,
+        };
+        Ok(parsed_packet_ref)
+    }
+}
+
+/// Location of the sections of a packet that passed validation.
+struct Sections {
+    offset_question: Option<usize>,
+    offset_answers: Option<usize>,
+    offset_nameservers: Option<usize>,
+    offset_additional: Option<usize>,
+    dropped_records: Option<DroppedRecords>,
+}
+
+impl<P: AsRef<[u8]>> DNSSector<P> {
+    /// Builds a `DNSSector` structure for a given untrusted DNS packet.
+    pub fn new(packet: P) -> Result<Self, Error> {
+        let dns_sector = DNSSector {
+            packet,
+            offset: 0,
+            edns_start: None,
+            edns_end: None,
+            edns_count: 0,
+            ext_rcode: None,
+            edns_version: None,
+            ext_flags: None,
+            max_payload: 512,
+            parse_options: ParseOptions::default(),
+        };
+        Ok(dns_sector)
+    }
+
+    /// Consumes the parser and return the original packet
+    pub fn into_packet(self) -> P {
+        self.packet
+    }
+
+    /// Returns the packet being parsed.
+    #[inline]
+    fn packet(&self) -> &[u8] {
+        self.packet.as_ref()
     }
 
     /// Returns the number of yet unparsed bytes.
     #[inline]
     fn remaining_len(&self) -> usize {
-        self.packet.len() - self.offset
+        self.packet().len() - self.offset
     }
 
     /// Makes sure that at least `len` bytes remain to be parsed.
@@ -119,7 +402,7 @@ impl DNSSector {
     /// Sets the internal offset to the data to be parsed to an arbitrary
     /// location
     pub fn set_offset(&mut self, offset: usize) -> Result<usize, Error> {
-        if offset >= self.packet.len() {
+        if offset >= self.packet().len() {
             bail!(DSError::InternalError(
                 "Setting offset past the end of the packet",
             ))
@@ -139,14 +422,14 @@ impl DNSSector {
     fn u8_load(&self, rr_offset: usize) -> Result<u8, Error> {
         self.ensure_remaining_len(rr_offset + 1)?;
         let offset = self.offset + rr_offset;
-        Ok(self.packet[offset])
+        Ok(self.packet()[offset])
     }
 
     #[inline]
     fn be16_load(&self, rr_offset: usize) -> Result<u16, Error> {
         self.ensure_remaining_len(rr_offset + 2)?;
         let offset = self.offset + rr_offset;
-        Ok(BigEndian::read_u16(&self.packet[offset..]))
+        Ok(BigEndian::read_u16(&self.packet()[offset..]))
     }
 
     #[allow(dead_code)]
@@ -154,14 +437,18 @@ impl DNSSector {
     fn be32_load(&self, rr_offset: usize) -> Result<u32, Error> {
         self.ensure_remaining_len(rr_offset + 4)?;
         let offset = self.offset + rr_offset;
-        Ok(BigEndian::read_u32(&self.packet[offset..]))
+        Ok(BigEndian::read_u32(&self.packet()[offset..]))
     }
 
     /// Checks that an encoded DNS name is valid. This includes following
     /// indirections for compressed names, checks for label lengths, checks
     /// for truncated names and checks for cycles.
     fn check_compressed_name(&self, offset: usize) -> Result<usize, Error> {
-        Compress::check_compressed_name(&self.packet, offset)
+        Compress::check_compressed_name_with_max_indirections(
+            self.packet(),
+            offset,
+            self.parse_options.limits.max_indirections,
+        )
     }
 
     /// Verifies that a name has been properly encoded, and sets the internal
@@ -196,119 +483,345 @@ impl DNSSector {
         self.be16_load(DNS_RR_RDLEN_OFFSET).map(|x| x as usize)
     }
 
-    /// Ensure that the record currently being parsed has the `IN` class.
+    /// Ensure that the record currently being parsed has a class allowed by
+    /// the parsing policy.
     #[inline]
-    fn ensure_in_class(&self) -> Result<(), Error> {
-        if self.rr_class()? != Class::IN.into() {
-            bail!(DSError::UnsupportedClass(self.rr_class().unwrap_or(0)));
+    fn ensure_allowed_class(&self) -> Result<(), Error> {
+        let rr_class = self.rr_class()?;
+        if !self.parse_options.is_class_allowed(rr_class) {
+            bail!(DSError::UnsupportedClass(rr_class));
         }
         Ok(())
     }
 
-    /// Builds a `DNSSector` structure for a given untrusted DNS packet.
-    pub fn new(packet: Vec<u8>) -> Result<Self, Error> {
-        let dns_sector = DNSSector {
-            packet,
-            offset: 0,
-            edns_start: None,
-            edns_end: None,
-            edns_count: 0,
-            ext_rcode: None,
-            edns_version: None,
-            ext_flags: None,
-            max_payload: 512,
-        };
-        Ok(dns_sector)
-    }
-
-    /// Parses and validates all records from all sections of an untrusted DNS
-    /// packet. If the validation succeeds, a `ParsedPacket` structure
-    /// containing information to quickly access (extended) flags and
-    /// individual sections is returned.
-    pub fn parse(mut self) -> Result<ParsedPacket, Error> {
-        let packet_len = self.packet.len();
+    /// Validates the header and the question section of the packet, using
+    /// `parse_options`, and returns the location of the question section.
+    /// The internal offset is left right after the question section.
+    fn parse_header_and_question(
+        &mut self,
+        parse_options: ParseOptions,
+    ) -> Result<Option<usize>, ParseError> {
+        self.parse_options = parse_options;
+        let packet_len = self.packet().len();
         if packet_len < DNS_HEADER_SIZE {
-            bail!(DSError::PacketTooSmall)
+            return Err(ParseError::new(DSError::PacketTooSmall, 0));
         }
-        let is_response = Self::is_response(&self.packet);
-        let qdcount = Self::qdcount(&self.packet);
-        if qdcount == 0 {
-            bail!(DSError::InvalidPacket(
-                "A DNS packet should contain a question",
+        let is_response = DNSSector::is_response(self.packet());
+        let allow_records = is_response || self.parse_options.allow_records_in_queries;
+        let qdcount = DNSSector::qdcount(self.packet());
+        if qdcount < self.parse_options.min_questions {
+            return Err(ParseError::new(
+                DSError::InvalidPacket("A DNS packet should contain a question"),
+                DNS_QDCOUNT_OFFSET,
             ));
         }
-        if qdcount > 1 {
-            bail!(DSError::InvalidPacket(
-                "A DNS packet cannot contain more than one question",
+        if qdcount > self.parse_options.max_questions {
+            return Err(ParseError::new(
+                DSError::InvalidPacket("Too many questions in a DNS packet"),
+                DNS_QDCOUNT_OFFSET,
             ));
         }
-        self.set_offset(DNS_QUESTION_OFFSET)?;
-        let offset_question = if qdcount > 0 { Some(self.offset) } else { None };
-        if qdcount != 0 {
-            assert_eq!(qdcount, 1);
-            self.parse_question()?;
+        self.check_limits()?;
+        let offset_question = if qdcount > 0 {
+            self.set_offset(DNS_QUESTION_OFFSET)
+                .map_err(|e| self.to_parse_error(e))?;
+            Some(self.offset)
+        } else {
+            self.offset = DNS_QUESTION_OFFSET;
+            None
+        };
+        for rr_index in 0..qdcount {
+            let rr_offset = self.offset;
+            self.parse_question()
+                .map_err(|e| self.locate_error(e, Section::Question, rr_index, rr_offset))?;
         }
-        let ancount = Self::ancount(&self.packet);
-        if !is_response && ancount > 0 {
-            bail!(DSError::InvalidPacket(
-                "A question shouldn't also contain answers"
+        let ancount = DNSSector::ancount(self.packet());
+        if !allow_records && ancount > 0 {
+            return Err(ParseError::new(
+                DSError::InvalidPacket("A question shouldn't also contain answers"),
+                DNS_ANCOUNT_OFFSET,
             ));
         }
-        let offset_answers = if ancount > 0 { Some(self.offset) } else { None };
-        for _ in 0..ancount {
-            self.parse_rr(Section::Answer)?;
+        let nscount = DNSSector::nscount(self.packet());
+        if !allow_records && nscount > 0 {
+            return Err(ParseError::new(
+                DSError::InvalidPacket("A question shouldn't also contain name servers"),
+                DNS_NSCOUNT_OFFSET,
+            ));
         }
-        let nscount = Self::nscount(&self.packet);
-        if !is_response && nscount > 0 {
-            bail!(DSError::InvalidPacket(
-                "A question shouldn't also contain name servers"
+        Ok(offset_question)
+    }
+
+    /// Validates all the sections of the packet, using `parse_options`, and
+    /// returns their location. When records have to be dropped from a
+    /// truncated response, the internal offset is left at the beginning of the
+    /// first dropped record.
+    fn parse_sections(&mut self, parse_options: ParseOptions) -> Result<Sections, ParseError> {
+        let offset_question = self.parse_header_and_question(parse_options)?;
+        let recover =
+            self.parse_options.recover_truncated && DNSSector::is_truncated(self.packet());
+        let ancount = DNSSector::ancount(self.packet());
+        let nscount = DNSSector::nscount(self.packet());
+        let arcount = DNSSector::arcount(self.packet());
+        let (offset_answers, ancount_kept) =
+            self.parse_section(Section::Answer, ancount, recover)?;
+        let (offset_nameservers, nscount_kept) = if ancount_kept == ancount {
+            self.parse_section(Section::NameServers, nscount, recover)?
+        } else {
+            (None, 0)
+        };
+        let (offset_additional, arcount_kept) =
+            if ancount_kept == ancount && nscount_kept == nscount {
+                self.parse_section(Section::Additional, arcount, recover)?
+            } else {
+                (None, 0)
+            };
+        let dropped_records = DroppedRecords {
+            answers: ancount - ancount_kept,
+            nameservers: nscount - nscount_kept,
+            additional: arcount - arcount_kept,
+        };
+        let dropped_records = if dropped_records.total() > 0 {
+            Some(dropped_records)
+        } else {
+            if self.remaining_len() > 0 {
+                return Err(ParseError::new(
+                    DSError::InvalidPacket("Extra data found after the last record"),
+                    self.offset,
+                ));
+            }
+            None
+        };
+        Ok(Sections {
+            offset_question,
+            offset_answers,
+            offset_nameservers,
+            offset_additional,
+            dropped_records,
+        })
+    }
+
+    /// Checks the packet size and the record counts from the header against
+    /// the limits set in the parsing options.
+    fn check_limits(&self) -> Result<(), ParseError> {
+        let limits = &self.parse_options.limits;
+        let packet = self.packet();
+        if packet.len() > limits.max_packet_size {
+            return Err(ParseError::new(
+                DSError::LimitExceeded("Packet too large"),
+                0,
             ));
         }
-        let offset_nameservers = if nscount > 0 { Some(self.offset) } else { None };
-        for _ in 0..nscount {
-            self.parse_rr(Section::NameServers)?;
+        for (count, count_offset) in [
+            (DNSSector::ancount(packet), DNS_ANCOUNT_OFFSET),
+            (DNSSector::nscount(packet), DNS_NSCOUNT_OFFSET),
+            (DNSSector::arcount(packet), DNS_ARCOUNT_OFFSET),
+        ] {
+            if count > limits.max_rrs_per_section {
+                return Err(ParseError::new(
+                    DSError::LimitExceeded("Too many records in a section"),
+                    count_offset,
+                ));
+            }
+        }
+        let rrs = DNSSector::qdcount(packet) as usize
+            + DNSSector::ancount(packet) as usize
+            + DNSSector::nscount(packet) as usize
+            + DNSSector::arcount(packet) as usize;
+        if rrs > limits.max_rrs {
+            return Err(ParseError::new(
+                DSError::LimitExceeded("Too many records"),
+                DNS_QDCOUNT_OFFSET,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parses `count` records from `section`, starting at the current offset.
+    /// Returns the offset of the first record, if there is any, and the number
+    /// of records that were parsed.
+    ///
+    /// If `recover` is `true`, parsing stops without an error at the first
+    /// record that doesn't entirely fit in the packet, and the internal offset
+    /// is left at the beginning of that record.
+    fn parse_section(
+        &mut self,
+        section: Section,
+        count: u16,
+        recover: bool,
+    ) -> Result<(Option<usize>, u16), ParseError> {
+        let offset_section = self.offset;
+        for rr_index in 0..count {
+            let rr_offset = self.offset;
+            if let Err(e) = self.parse_rr(section) {
+                if recover && self.is_incomplete_rr(rr_offset) {
+                    self.offset = rr_offset;
+                    if !matches!(self.edns_start, Some(edns_start) if edns_start < rr_offset) {
+                        self.reset_edns();
+                    }
+                    let offset_section = if rr_index > 0 {
+                        Some(offset_section)
+                    } else {
+                        None
+                    };
+                    return Ok((offset_section, rr_index));
+                }
+                return Err(self.locate_error(e, section, rr_index, rr_offset));
+            }
+        }
+        let offset_section = if count > 0 {
+            Some(offset_section)
+        } else {
+            None
+        };
+        Ok((offset_section, count))
+    }
+
+    /// Checks if the record starting at `offset` is cut by the end of the
+    /// packet.
+    fn is_incomplete_rr(&self, offset: usize) -> bool {
+        self.skip_rr_unchecked(offset).is_none()
+    }
+
+    /// Skips over the record starting at `offset` without validating it.
+    /// Compression pointers are not followed, since only the location of the
+    /// data following the name matters. Returns the location right after the
+    /// name and the location of the next record, or `None` if the record
+    /// doesn't entirely fit in the packet.
+    fn skip_rr_unchecked(&self, mut offset: usize) -> Option<(usize, usize)> {
+        let packet = self.packet();
+        let packet_len = packet.len();
+        loop {
+            if offset >= packet_len {
+                return None;
+            }
+            match packet[offset] {
+                0 => {
+                    offset += 1;
+                    break;
+                }
+                len if len & 0xc0 == 0xc0 => {
+                    offset += 2;
+                    break;
+                }
+                len => offset += 1 + (len & 0x3f) as usize,
+            }
+        }
+        if offset + DNS_RR_HEADER_SIZE > packet_len {
+            return None;
+        }
+        let rdlen = BigEndian::read_u16(&packet[offset + DNS_RR_RDLEN_OFFSET..]) as usize;
+        let offset_next = offset + DNS_RR_HEADER_SIZE + rdlen;
+        if offset_next > packet_len {
+            return None;
+        }
+        Some((offset, offset_next))
+    }
+
+    /// Finds and validates the `OPT` record, if there is one, by skipping over
+    /// the records following the question section without validating them.
+    /// Fails if these records don't exactly fill the rest of the packet.
+    fn find_opt(&mut self) -> Result<(), Error> {
+        let packet = self.packet();
+        let skipped = DNSSector::ancount(packet) as usize + DNSSector::nscount(packet) as usize;
+        let arcount = DNSSector::arcount(packet);
+        let mut offset = self.offset;
+        for _ in 0..skipped {
+            let (_, offset_next) = self
+                .skip_rr_unchecked(offset)
+                .ok_or(DSError::PacketTooSmall)?;
+            offset = offset_next;
         }
-        let arcount = Self::arcount(&self.packet);
-        let offset_additional = if arcount > 0 { Some(self.offset) } else { None };
         for _ in 0..arcount {
-            self.parse_rr(Section::Additional)?;
+            let (name_end, offset_next) = self
+                .skip_rr_unchecked(offset)
+                .ok_or(DSError::PacketTooSmall)?;
+            let rr_type = BigEndian::read_u16(&self.packet()[name_end + DNS_RR_TYPE_OFFSET..]);
+            if rr_type == Type::OPT.into() {
+                self.offset = offset;
+                self.parse_rr(Section::Additional)?;
+            }
+            offset = offset_next;
         }
-        if self.remaining_len() > 0 {
+        if offset != self.packet().len() {
             bail!(DSError::InvalidPacket(
-                "Extra data found after the last record",
+                "Extra data found after the last record"
             ));
         }
-        let edns_start = self.edns_start;
-        let ext_rcode = self.ext_rcode;
-        let edns_version = self.edns_version;
-        let ext_flags = self.ext_flags;
-        let edns_count = self.edns_count;
-        let max_payload = self.max_payload;
-        let parsed_packet = ParsedPacket {
-            packet: Some(self.packet),
-            offset_question,
-            offset_answers,
-            offset_nameservers,
-            offset_additional,
-            offset_edns: edns_start,
-            ext_rcode,
-            edns_version,
-            ext_flags,
-            edns_count,
-            maybe_compressed: true,
-            max_payload,
-            cached: None,
-        };
-        Ok(parsed_packet)
+        Ok(())
+    }
+
+    /// Forgets about a partially parsed `OPT` record.
+    fn reset_edns(&mut self) {
+        self.edns_start = None;
+        self.edns_end = None;
+        self.edns_count = 0;
+        self.ext_rcode = None;
+        self.edns_version = None;
+        self.ext_flags = None;
+        self.max_payload = 512;
+    }
+
+    /// Converts an error raised while parsing into a `ParseError`. Errors that
+    /// don't already carry the offset of the offending field are attributed to
+    /// the current offset, which is only an approximation: it is the field
+    /// the parser was at when the error was raised.
+    fn to_parse_error(&self, error: Error) -> ParseError {
+        match error.downcast::<ParseError>() {
+            Ok(parse_error) => parse_error,
+            Err(error) => {
+                let kind = error
+                    .downcast::<DSError>()
+                    .unwrap_or(DSError::InternalError("Unexpected error type"));
+                ParseError::new(kind, self.offset)
+            }
+        }
+    }
+
+    /// Converts an error raised while parsing the record starting at
+    /// `rr_offset` into a `ParseError` that includes the section, the index
+    /// of the record within that section, and its type if it can be read.
+    fn locate_error(
+        &self,
+        error: Error,
+        section: Section,
+        rr_index: u16,
+        rr_offset: usize,
+    ) -> ParseError {
+        let mut parse_error = self.to_parse_error(error);
+        parse_error.section = Some(section);
+        parse_error.rr_index = Some(rr_index);
+        parse_error.rr_type = self
+            .name_end_in_place(rr_offset)
+            .and_then(|name_end| {
+                self.packet()
+                    .get(name_end + DNS_RR_TYPE_OFFSET..name_end + DNS_RR_TYPE_OFFSET + 2)
+            })
+            .map(BigEndian::read_u16);
+        parse_error
+    }
+
+    /// Returns the location right after the name starting at `offset`,
+    /// without following compression pointers, so that the type of a record
+    /// can be found even if its name is invalid.
+    fn name_end_in_place(&self, mut offset: usize) -> Option<usize> {
+        let packet = self.packet();
+        loop {
+            match *packet.get(offset)? {
+                0 => return Some(offset + 1),
+                len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+                len if len > 0x3f => return None,
+                len => offset += 1 + len as usize,
+            }
+        }
     }
 
     /// Parses a question RR.
     fn parse_question(&mut self) -> Result<(), Error> {
         self.skip_name()?;
-        self.ensure_in_class()?;
-        if self.rr_class()? != Class::IN.into() {
-            bail!(DSError::UnsupportedClass(self.rr_class().unwrap_or(0)));
-        }
+        let class_offset = self.offset + DNS_RR_CLASS_OFFSET;
+        self.ensure_allowed_class()
+            .map_err(|e| DNSSector::locate(e, class_offset))?;
         self.increment_offset(DNS_RR_QUESTION_HEADER_SIZE)?;
         Ok(())
     }
@@ -319,91 +832,111 @@ impl DNSSector {
         self.skip_name()?;
         let rr_type = self.rr_type()?;
         let rr_rdlen = self.rr_rdlen()?;
+        let rdlen_offset = self.offset + DNS_RR_RDLEN_OFFSET;
+        let rdata_offset = self.offset + DNS_RR_HEADER_SIZE;
+        let rdlen_error = |e| DNSSector::locate(e, rdlen_offset);
         match rr_type {
             x if x == Type::OPT.into() => {
                 if section != Section::Additional {
-                    bail!(DSError::InvalidPacket(
-                        "OPT RRs must be in the additional section"
+                    bail!(DNSSector::error_at(
+                        DSError::InvalidPacket("OPT RRs must be in the additional section"),
+                        rr_start_offset
                     ));
                 }
                 if self.offset - rr_start_offset != 1 {
-                    bail!(DSError::InvalidPacket(
-                        "OPT RRs must have the root domain as the domain name"
+                    bail!(DNSSector::error_at(
+                        DSError::InvalidPacket(
+                            "OPT RRs must have the root domain as the domain name"
+                        ),
+                        rr_start_offset
                     ));
                 }
                 return self.parse_opt();
             }
             x if x == Type::NS.into() || x == Type::CNAME.into() || x == Type::PTR.into() => {
                 if rr_rdlen == 0 {
-                    bail!(DSError::PacketTooSmall);
+                    bail!(DNSSector::error_at(DSError::PacketTooSmall, rdlen_offset));
                 }
                 self.increment_offset(DNS_RR_HEADER_SIZE)?;
-                let final_offset = Compress::check_compressed_name(&self.packet, self.offset)?;
+                let final_offset = self.check_compressed_name(self.offset)?;
                 if final_offset - self.offset != rr_rdlen {
-                    bail!(DSError::InvalidPacket(
-                        "Unexpected data after name in rdata",
+                    bail!(DNSSector::error_at(
+                        DSError::InvalidPacket("Unexpected data after name in rdata"),
+                        rdata_offset
                     ))
                 }
-                self.increment_offset(rr_rdlen)?;
+                self.increment_offset(rr_rdlen).map_err(rdlen_error)?;
             }
             x if x == Type::MX.into() => {
                 if rr_rdlen <= 2 {
-                    bail!(DSError::PacketTooSmall);
+                    bail!(DNSSector::error_at(DSError::PacketTooSmall, rdlen_offset));
                 }
                 self.increment_offset(DNS_RR_HEADER_SIZE)?;
-                let final_offset = Compress::check_compressed_name(&self.packet, self.offset + 2)?;
+                let final_offset = self.check_compressed_name(self.offset + 2)?;
                 if final_offset - self.offset != rr_rdlen {
-                    bail!(DSError::InvalidPacket(
-                        "Unexpected data after name in MX rdata",
+                    bail!(DNSSector::error_at(
+                        DSError::InvalidPacket("Unexpected data after name in MX rdata"),
+                        rdata_offset
                     ))
                 }
-                self.increment_offset(rr_rdlen)?;
+                self.increment_offset(rr_rdlen).map_err(rdlen_error)?;
             }
             x if x == Type::SOA.into() => {
                 if rr_rdlen <= 1 + 20 {
-                    bail!(DSError::PacketTooSmall);
+                    bail!(DNSSector::error_at(DSError::PacketTooSmall, rdlen_offset));
                 }
                 self.increment_offset(DNS_RR_HEADER_SIZE)?;
-                let final_offset_1 = Compress::check_compressed_name(&self.packet, self.offset)?;
-                let final_offset_2 = Compress::check_compressed_name(&self.packet, final_offset_1)?;
+                let final_offset_1 = self.check_compressed_name(self.offset)?;
+                let final_offset_2 = self.check_compressed_name(final_offset_1)?;
                 if final_offset_2 - self.offset != rr_rdlen - 20 {
-                    bail!(DSError::InvalidPacket(
-                        "Unexpected data after name in SOA rdata",
+                    bail!(DNSSector::error_at(
+                        DSError::InvalidPacket("Unexpected data after name in SOA rdata"),
+                        rdata_offset
                     ))
                 }
-                self.increment_offset(rr_rdlen)?;
+                self.increment_offset(rr_rdlen).map_err(rdlen_error)?;
             }
             x if x == Type::DNAME.into() => {
                 if rr_rdlen == 0 {
-                    bail!(DSError::PacketTooSmall);
+                    bail!(DNSSector::error_at(DSError::PacketTooSmall, rdlen_offset));
                 }
                 self.increment_offset(DNS_RR_HEADER_SIZE)?;
-                let final_offset = Self::check_uncompressed_name(&self.packet, self.offset)?;
+                let final_offset = DNSSector::check_uncompressed_name(self.packet(), self.offset)?;
                 if final_offset - self.offset != rr_rdlen {
-                    bail!(DSError::InvalidPacket(
-                        "Unexpected data after name in DNAME rdata",
+                    bail!(DNSSector::error_at(
+                        DSError::InvalidPacket("Unexpected data after name in DNAME rdata"),
+                        rdata_offset
                     ))
                 }
-                self.increment_offset(rr_rdlen)?;
+                self.increment_offset(rr_rdlen).map_err(rdlen_error)?;
             }
             x if x == Type::A.into() => {
                 if rr_rdlen != 4 {
-                    bail!(DSError::InvalidPacket(
-                        "A record doesn't include a 4 bytes IP address"
+                    bail!(DNSSector::error_at(
+                        DSError::InvalidPacket("A record doesn't include a 4 bytes IP address"),
+                        rdlen_offset
                     ))
                 }
-                self.increment_offset(DNS_RR_HEADER_SIZE + rr_rdlen)?;
+                self.increment_offset(DNS_RR_HEADER_SIZE + rr_rdlen)
+                    .map_err(rdlen_error)?;
             }
             x if x == Type::AAAA.into() => {
                 if rr_rdlen != 16 {
-                    bail!(DSError::InvalidPacket(
-                        "AAAA record doesn't include a 16 bytes IP address"
+                    bail!(DNSSector::error_at(
+                        DSError::InvalidPacket("AAAA record doesn't include a 16 bytes IP address"),
+                        rdlen_offset
                     ))
                 }
-                self.increment_offset(DNS_RR_HEADER_SIZE + rr_rdlen)?;
+                self.increment_offset(DNS_RR_HEADER_SIZE + rr_rdlen)
+                    .map_err(rdlen_error)?;
             }
             _ => {
-                self.increment_offset(DNS_RR_HEADER_SIZE + rr_rdlen)?;
+                self.ensure_remaining_len(DNS_RR_HEADER_SIZE + rr_rdlen)
+                    .map_err(rdlen_error)?;
+                RdataValidator::check(rr_type, self.packet(), rdata_offset, rr_rdlen)
+                    .map_err(|e| DNSSector::locate(e, rdata_offset))?;
+                self.increment_offset(DNS_RR_HEADER_SIZE + rr_rdlen)
+                    .map_err(rdlen_error)?;
             }
         }
         Ok(())
@@ -440,7 +973,7 @@ impl DNSSector {
     fn edns_be16_load(&self, rr_offset: usize) -> Result<u16, Error> {
         self.edns_ensure_remaining_len(rr_offset + 2)?;
         let offset = self.offset + rr_offset;
-        Ok(((self.packet[offset] as u16) << 8) | self.packet[offset + 1] as u16)
+        Ok(((self.packet()[offset] as u16) << 8) | self.packet()[offset + 1] as u16)
     }
 
     #[allow(dead_code)]
@@ -448,10 +981,10 @@ impl DNSSector {
     fn edns_be32_load(&self, rr_offset: usize) -> Result<u32, Error> {
         self.edns_ensure_remaining_len(rr_offset + 4)?;
         let offset = self.offset + rr_offset;
-        Ok(((self.packet[offset] as u32) << 24)
-            | ((self.packet[offset + 1] as u32) << 16)
-            | ((self.packet[offset + 2] as u32) << 8)
-            | self.packet[offset + 3] as u32)
+        Ok(((self.packet()[offset] as u32) << 24)
+            | ((self.packet()[offset + 1] as u32) << 16)
+            | ((self.packet()[offset + 2] as u32) << 8)
+            | self.packet()[offset + 3] as u32)
     }
 
     /// Returns the extended code of a record within the edns pseudo-section.
@@ -524,45 +1057,13 @@ impl DNSSector {
         self.edns_end = Some(self.offset + edns_len);
         self.edns_count = 0;
         while self.edns_remaining_len() > 0 {
+            if self.edns_count >= self.parse_options.limits.max_edns_options {
+                bail!(DSError::LimitExceeded("Too many edns options"));
+            }
             self.edns_skip_rr()?;
             self.edns_count += 1;
         }
         debug_assert_eq!(self.edns_remaining_len(), 0);
         Ok(())
     }
-
-    /// Checks that an untrusted encoded DNS name is valid and does not contain
-    /// any indirections. Returns the location right after the name.
-    pub fn check_uncompressed_name(packet: &[u8], mut offset: usize) -> Result<usize, Error> {
-        let packet_len = packet.len();
-        let mut name_len = 0;
-        if offset >= packet_len {
-            bail!(DSError::InternalError("Offset outside packet boundaries"));
-        }
-        if 1 > packet_len - offset {
-            bail!(DSError::InvalidName("Empty name"));
-        }
-        loop {
-            if offset >= packet_len {
-                bail!(DSError::InvalidName("Truncated name"));
-            }
-            let label_len = match packet[offset] {
-                len if len & 0xc0 == 0xc0 => bail!(DSError::InvalidName("Unexpected compression")),
-                len if len > 0x3f => bail!(DSError::InvalidName("Label length too long")),
-                len => len as usize,
-            };
-            if label_len >= packet_len - offset {
-                bail!(DSError::InvalidName("Out-of-bounds name"));
-            }
-            name_len += label_len + 1;
-            if name_len > DNS_MAX_HOSTNAME_LEN {
-                bail!(DSError::InvalidName("Name too long"));
-            }
-            offset += label_len + 1;
-            if label_len == 0 {
-                break;
-            }
-        }
-        Ok(offset)
-    }
 }
@@ -1,6 +1,10 @@
+use std::fmt;
+
 pub use anyhow::{anyhow, bail, ensure, Error};
 
-#[derive(Debug, thiserror::Error)]
+use crate::constants::*;
+
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
 pub enum DSError {
     #[error("Packet too small")]
     PacketTooSmall,
@@ -26,4 +30,70 @@ pub enum DSError {
     WrongAddressFamily,
     #[error("Parse error")]
     ParseError,
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(&'static str),
+    #[error("Invalid IP prefix: {0}")]
+    InvalidPrefix(&'static str),
+}
+
+/// A `ParseError` is a `DSError` raised while validating an untrusted packet,
+/// along with the location it was raised at.
+///
+/// `offset` is the start of the offending field, such as a label or the
+/// RDLENGTH of a record. Errors that can't be tied to a single field are
+/// reported at the field the parser was at, so their offset is approximate.
+///
+/// `section`, `rr_index` and `rr_type` are `None` when the error isn't
+/// related to a specific record, or when they couldn't be recovered from the
+/// packet.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub kind: DSError,
+    pub offset: usize,
+    pub section: Option<Section>,
+    pub rr_index: Option<u16>,
+    pub rr_type: Option<u16>,
 }
+
+impl ParseError {
+    /// Creates a new error raised at `offset`, not attached to any record yet.
+    pub fn new(kind: DSError, offset: usize) -> Self {
+        ParseError {
+            kind,
+            offset,
+            section: None,
+            rr_index: None,
+            rr_type: None,
+        }
+    }
+
+    /// Converts the error into a generic `Error`.
+    /// Both `DSError` and `ParseError` can be retrieved from the result using
+    /// `downcast()` and `downcast_ref()`.
+    pub fn into_error(self) -> Error {
+        Error::new(self.kind.clone()).context(self)
+    }
+
+    /// Returns the `ParseError` attached to a generic `Error`, if there is one.
+    pub fn from_error(error: &Error) -> Option<&ParseError> {
+        error.downcast_ref::<ParseError>()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (offset: {}", self.kind, self.offset)?;
+        if let Some(section) = self.section {
+            write!(f, ", section: {:?}", section)?;
+        }
+        if let Some(rr_index) = self.rr_index {
+            write!(f, ", record: {}", rr_index)?;
+        }
+        if let Some(rr_type) = self.rr_type {
+            write!(f, ", type: {}", rr_type)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl std::error::Error for ParseError {}
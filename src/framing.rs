@@ -0,0 +1,162 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::constants::*;
+use crate::dns_sector::*;
+use crate::errors::*;
+use crate::parse_options::*;
+use crate::parsed_packet::*;
+
+/// Size of the length prefix of messages sent over a stream.
+const DNS_STREAM_LENGTH_PREFIX_SIZE: usize = 2;
+
+/// Stream-based transports, on which DNS messages are prefixed with their
+/// length as a 16-bit big-endian integer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Transport {
+    /// DNS over TCP (RFC 1035 section 4.2.2).
+    Tcp,
+    /// DNS over TLS (RFC 7858).
+    Tls,
+    /// DNS over QUIC (RFC 9250): the message ID must always be zero.
+    Quic,
+}
+
+impl Transport {
+    /// Returns `true` if messages must have a zero message ID.
+    #[inline]
+    pub fn requires_zero_tid(&self) -> bool {
+        *self == Transport::Quic
+    }
+}
+
+/// A `StreamCodec` reassembles length-prefixed DNS messages from a byte
+/// stream, and encodes packets with their length prefix.
+///
+/// Data can be fed in chunks of any size, and a message can be split across
+/// chunks. Messages larger than the maximum packet size set in the parsing
+/// limits are rejected as soon as their length is known: since the stream
+/// cannot be resynchronized after that, the same error is returned until the
+/// codec is dropped, and the connection should be closed.
+#[derive(Clone, Debug)]
+pub struct StreamCodec {
+    transport: Transport,
+    parse_options: ParseOptions,
+    buffer: Vec<u8>,
+}
+
+impl StreamCodec {
+    /// Creates a codec for `transport`, accepting messages up to
+    /// `DNS_MAX_PACKET_SIZE` bytes.
+    pub fn new(transport: Transport) -> Self {
+        StreamCodec {
+            transport,
+            parse_options: ParseOptions::default(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Sets the options used to parse messages. Their maximum packet size
+    /// is also the maximum size of messages, both decoded and encoded.
+    pub fn with_parse_options(mut self, parse_options: ParseOptions) -> Self {
+        self.parse_options = parse_options;
+        self
+    }
+
+    /// Returns the transport this codec was created for.
+    #[inline]
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// Returns the maximum size of a message, excluding its length prefix.
+    #[inline]
+    pub fn max_message_size(&self) -> usize {
+        self.parse_options
+            .limits
+            .max_packet_size
+            .min(DNS_MAX_PACKET_SIZE)
+    }
+
+    /// Returns the number of buffered bytes that haven't been returned as
+    /// messages yet.
+    #[inline]
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Appends data received from the stream.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns the next complete message, without its length prefix, or
+    /// `None` if more data is needed.
+    ///
+    /// The message is only checked for its size, and for its message ID over
+    /// QUIC. It hasn't been parsed yet.
+    pub fn next_message(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if self.buffer.len() < DNS_STREAM_LENGTH_PREFIX_SIZE {
+            return Ok(None);
+        }
+        let message_len = BigEndian::read_u16(&self.buffer) as usize;
+        if message_len > self.max_message_size() {
+            bail!(DSError::LimitExceeded("Message too large"));
+        }
+        let frame_len = DNS_STREAM_LENGTH_PREFIX_SIZE + message_len;
+        if self.buffer.len() < frame_len {
+            return Ok(None);
+        }
+        let message = self.buffer[DNS_STREAM_LENGTH_PREFIX_SIZE..frame_len].to_vec();
+        self.buffer.drain(..frame_len);
+        self.check_message(&message)?;
+        Ok(Some(message))
+    }
+
+    /// Returns the next complete message as a `DNSSector`, or `None` if more
+    /// data is needed.
+    pub fn next_dns_sector(&mut self) -> Result<Option<DNSSector>, Error> {
+        match self.next_message()? {
+            None => Ok(None),
+            Some(message) => DNSSector::new(message).map(Some),
+        }
+    }
+
+    /// Returns the next complete message, parsed and validated with the
+    /// codec's parsing options, or `None` if more data is needed.
+    pub fn next_parsed_packet(&mut self) -> Result<Option<ParsedPacket>, Error> {
+        match self.next_dns_sector()? {
+            None => Ok(None),
+            Some(dns_sector) => dns_sector.parse_with(self.parse_options).map(Some),
+        }
+    }
+
+    /// Appends `parsed_packet` to `out`, prefixed with its length.
+    pub fn encode(&self, parsed_packet: &ParsedPacket, out: &mut Vec<u8>) -> Result<(), Error> {
+        let message = parsed_packet.packet();
+        if message.len() > self.max_message_size() {
+            bail!(DSError::LimitExceeded("Message too large"));
+        }
+        self.check_message(message)?;
+        let mut prefix = [0u8; DNS_STREAM_LENGTH_PREFIX_SIZE];
+        BigEndian::write_u16(&mut prefix, message.len() as u16);
+        out.reserve(prefix.len() + message.len());
+        out.extend_from_slice(&prefix);
+        out.extend_from_slice(message);
+        Ok(())
+    }
+
+    /// Checks the rules that depend on the transport.
+    fn check_message(&self, message: &[u8]) -> Result<(), Error> {
+        if message.len() < DNS_HEADER_SIZE {
+            bail!(DSError::PacketTooSmall);
+        }
+        if self.transport.requires_zero_tid()
+            && BigEndian::read_u16(&message[DNS_TID_OFFSET..]) != 0
+        {
+            bail!(DSError::InvalidPacket(
+                "Messages sent over QUIC must have a zero message ID"
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::errors::*;
+
+/// Special-use IPv4 prefixes, that are not globally reachable (RFC 6890).
+pub(crate) const SPECIAL_USE_IPV4_PREFIXES: [&str; 14] = [
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+];
+
+/// Special-use IPv6 prefixes, that are not globally reachable (RFC 6890),
+/// including IPv4-mapped addresses.
+pub(crate) const SPECIAL_USE_IPV6_PREFIXES: [&str; 10] = [
+    "::/128",
+    "::1/128",
+    "::ffff:0:0/96",
+    "64:ff9b:1::/48",
+    "100::/64",
+    "2001:db8::/32",
+    "fc00::/7",
+    "fe80::/10",
+    "fec0::/10",
+    "ff00::/8",
+];
+
+/// An `IpPrefix` is an IPv4 or IPv6 network, such as `192.0.2.0/24`.
+/// The bits of the address after the first `len` bits are always zero.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct IpPrefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl IpPrefix {
+    /// Creates a prefix made of the first `len` bits of `addr`. `len` can't
+    /// exceed the size of the address.
+    pub fn new(addr: IpAddr, len: u8) -> Result<Self, Error> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if len > max_len {
+            bail!(DSError::InvalidPrefix("Prefix length too large"));
+        }
+        let addr = match addr {
+            IpAddr::V4(addr) => IpAddr::V4(Ipv4Addr::from(u32::from(addr) & Self::mask_v4(len))),
+            IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from(u128::from(addr) & Self::mask_v6(len))),
+        };
+        Ok(IpPrefix { addr, len })
+    }
+
+    /// Creates a prefix from an address whose bits after the first `len`
+    /// bits are already zero, and a `len` that doesn't exceed its size.
+    pub(crate) const fn new_unchecked(addr: IpAddr, len: u8) -> Self {
+        IpPrefix { addr, len }
+    }
+
+    /// Returns the address of the network.
+    #[inline]
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Returns the length of the prefix, in bits.
+    #[inline]
+    pub fn prefix_len(&self) -> u8 {
+        self.len
+    }
+
+    /// Returns `true` if this is an IPv4 prefix.
+    #[inline]
+    pub fn is_ipv4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+
+    /// Returns `true` if `ip` belongs to this prefix. Addresses from another
+    /// family never do.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                u32::from(*ip) & Self::mask_v4(self.len) == u32::from(addr)
+            }
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                u128::from(*ip) & Self::mask_v6(self.len) == u128::from(addr)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the address of `target` with the same host bits as `ip`, if
+    /// `ip` belongs to this prefix and `target` has the same family and
+    /// length.
+    pub fn translate(&self, ip: &IpAddr, target: &IpPrefix) -> Option<IpAddr> {
+        if !self.contains(ip) || self.len != target.len {
+            return None;
+        }
+        match (ip, target.addr) {
+            (IpAddr::V4(ip), IpAddr::V4(target)) => Some(IpAddr::V4(Ipv4Addr::from(
+                u32::from(*ip) & !Self::mask_v4(self.len) | u32::from(target),
+            ))),
+            (IpAddr::V6(ip), IpAddr::V6(target)) => Some(IpAddr::V6(Ipv6Addr::from(
+                u128::from(*ip) & !Self::mask_v6(self.len) | u128::from(target),
+            ))),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn mask_v4(len: u8) -> u32 {
+        u32::MAX
+            .checked_shl(32u32.saturating_sub(len as u32))
+            .unwrap_or(0)
+    }
+
+    #[inline]
+    fn mask_v6(len: u8) -> u128 {
+        u128::MAX
+            .checked_shl(128u32.saturating_sub(len as u32))
+            .unwrap_or(0)
+    }
+}
+
+impl FromStr for IpPrefix {
+    type Err = Error;
+
+    /// Parses a prefix such as `192.0.2.0/24` or `2001:db8::/32`. An address
+    /// without a length is a prefix for that single address.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (addr, len) = match s.split_once('/') {
+            None => (s, None),
+            Some((addr, len)) => (addr, Some(len)),
+        };
+        let addr: IpAddr = match addr.parse() {
+            Err(_) => bail!(DSError::InvalidPrefix("Invalid address")),
+            Ok(addr) => addr,
+        };
+        let len = match len {
+            None if addr.is_ipv4() => 32,
+            None => 128,
+            Some(len) => match len.parse() {
+                Err(_) => bail!(DSError::InvalidPrefix("Invalid prefix length")),
+                Ok(len) => len,
+            },
+        };
+        IpPrefix::new(addr, len)
+    }
+}
+
+impl fmt::Display for IpPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+/// Returns the `in-addr.arpa` or `ip6.arpa` name of `ip`, with dot-delimited
+/// labels and without a trailing dot.
+pub fn reverse_name(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            format!(
+                "{}.{}.{}.{}.in-addr.arpa",
+                octets[3], octets[2], octets[1], octets[0]
+            )
+            .into_bytes()
+        }
+        IpAddr::V6(ip) => {
+            let mut name = Vec::with_capacity(32 * 2 + 8);
+            for octet in ip.octets().iter().rev() {
+                for nibble in [octet & 0xf, octet >> 4] {
+                    name.push(b"0123456789abcdef"[nibble as usize]);
+                    name.push(b'.');
+                }
+            }
+            name.extend_from_slice(b"ip6.arpa");
+            name
+        }
+    }
+}
+
+/// Returns the address whose `in-addr.arpa` or `ip6.arpa` name is `name`,
+/// or `None` if `name` is not the name of a complete address. Names are
+/// dot-delimited, without a trailing dot, and compared case-insensitively.
+pub fn reverse_name_to_ip(name: &[u8]) -> Option<IpAddr> {
+    let name = name.to_ascii_lowercase();
+    if let Some(labels) = name.strip_suffix(b".in-addr.arpa") {
+        let mut octets = [0u8; 4];
+        let mut labels = labels.split(|&c| c == b'.');
+        for octet in octets.iter_mut().rev() {
+            let label = std::str::from_utf8(labels.next()?).ok()?;
+            if label.len() > 1 && label.starts_with('0') {
+                return None;
+            }
+            *octet = label.parse().ok()?;
+        }
+        if labels.next().is_some() {
+            return None;
+        }
+        return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+    let labels = name.strip_suffix(b".ip6.arpa")?;
+    if labels.len() != 32 * 2 - 1 {
+        return None;
+    }
+    let mut ip = 0u128;
+    for (i, label) in labels.split(|&c| c == b'.').enumerate() {
+        let nibble = match label {
+            [c] => (*c as char).to_digit(16)?,
+            _ => return None,
+        };
+        ip |= (nibble as u128) << (i * 4);
+    }
+    Some(IpAddr::V6(Ipv6Addr::from(ip)))
+}
@@ -17,28 +17,60 @@
 #[macro_use]
 extern crate chomp;
 
+pub mod address_mapping;
 pub mod c_abi;
+pub mod cache;
 pub mod compress;
 pub mod constants;
+pub mod dns64;
 pub mod dns_sector;
 pub mod edns_iterator;
 pub mod errors;
+pub mod framing;
+pub mod ip_prefix;
+pub mod limits;
+pub mod matching;
+pub mod minimal_responses;
+pub mod parse_options;
 pub mod parsed_packet;
+pub mod parsed_packet_ref;
 pub mod question_iterator;
+pub mod rdata_validator;
+pub mod rebinding;
+pub mod record_view;
 pub mod renamer;
 pub mod response_iterator;
+pub mod response_options;
 pub mod rr_iterator;
+pub mod shuffle;
 pub mod synth;
+pub mod truncation;
 
+pub use crate::address_mapping::*;
 pub use crate::c_abi::*;
+pub use crate::cache::*;
 pub use crate::compress::*;
 pub use crate::constants::*;
+pub use crate::dns64::*;
 pub use crate::dns_sector::*;
 pub use crate::edns_iterator::*;
 pub use crate::errors::*;
+pub use crate::framing::*;
+pub use crate::ip_prefix::*;
+pub use crate::limits::*;
+pub use crate::matching::*;
+pub use crate::minimal_responses::*;
+pub use crate::parse_options::*;
 pub use crate::parsed_packet::*;
+pub use crate::parsed_packet_ref::*;
 pub use crate::question_iterator::*;
+pub use crate::rdata_validator::*;
+pub use crate::rebinding::*;
+pub use crate::record_view::*;
 pub use crate::renamer::*;
 pub use crate::response_iterator::*;
+pub use crate::response_options::*;
 pub use crate::rr_iterator::*;
+pub use crate::shuffle::*;
 pub use crate::synth::*;
+pub use crate::truncation::*;
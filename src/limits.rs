@@ -0,0 +1,71 @@
+use crate::constants::*;
+
+/// A `Limits` structure bounds the resources a single packet may use, both
+/// when it is parsed by `DNSSector` and when it is modified as a
+/// `ParsedPacket`. It is part of `ParseOptions`.
+///
+/// Exceeding any of these limits results in a `DSError::LimitExceeded` error.
+/// The default limits are the largest values allowed by the protocol, and
+/// are suitable for TCP, so that they only apply when they are explicitly
+/// lowered, for example for UDP frontends.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// Maximum size of a packet, either received or after modifications.
+    pub max_packet_size: usize,
+    /// Maximum number of records in the answer, name servers and additional
+    /// sections, each. The question section is bounded by
+    /// `ParseOptions::max_questions` instead.
+    pub max_rrs_per_section: u16,
+    /// Maximum number of records in all sections combined.
+    pub max_rrs: usize,
+    /// Maximum number of compression pointers followed in a single name.
+    /// With the default value, longer chains are reported as
+    /// `DSError::InvalidName`, like any other invalid name.
+    pub max_indirections: u16,
+    /// Maximum number of options in the edns pseudo-section.
+    pub max_edns_options: u16,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_packet_size: DNS_MAX_PACKET_SIZE,
+            max_rrs_per_section: 0xffff,
+            max_rrs: 4 * 0xffff,
+            max_indirections: DNS_MAX_HOSTNAME_INDIRECTIONS,
+            max_edns_options: 0xffff,
+        }
+    }
+}
+
+impl Limits {
+    /// Sets the maximum size of a packet.
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Sets the maximum number of records per section.
+    pub fn with_max_rrs_per_section(mut self, max_rrs_per_section: u16) -> Self {
+        self.max_rrs_per_section = max_rrs_per_section;
+        self
+    }
+
+    /// Sets the maximum number of records in a packet.
+    pub fn with_max_rrs(mut self, max_rrs: usize) -> Self {
+        self.max_rrs = max_rrs;
+        self
+    }
+
+    /// Sets the maximum number of compression pointers followed in a name.
+    pub fn with_max_indirections(mut self, max_indirections: u16) -> Self {
+        self.max_indirections = max_indirections;
+        self
+    }
+
+    /// Sets the maximum number of edns options.
+    pub fn with_max_edns_options(mut self, max_edns_options: u16) -> Self {
+        self.max_edns_options = max_edns_options;
+        self
+    }
+}
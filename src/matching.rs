@@ -0,0 +1,39 @@
+/// A `MatchPolicy` describes how a response is compared to the query it is
+/// supposed to answer, by `ParsedPacket::validate_response_for()`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MatchPolicy {
+    /// Whether names must have the exact same case. This should be set when
+    /// the query name was randomized with 0x20 encoding.
+    pub exact_case: bool,
+}
+
+impl MatchPolicy {
+    /// A policy comparing names case-insensitively.
+    pub fn case_insensitive() -> Self {
+        MatchPolicy { exact_case: false }
+    }
+
+    /// A policy requiring names to have the exact same case.
+    pub fn exact_case() -> Self {
+        MatchPolicy { exact_case: true }
+    }
+}
+
+/// The reason why a response doesn't match a query.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum ResponseMismatch {
+    #[error("The packet is not a response")]
+    NotAResponse,
+    #[error("Transaction ID mismatch")]
+    Tid,
+    #[error("Opcode mismatch")]
+    Opcode,
+    #[error("Question count mismatch")]
+    QuestionCount,
+    #[error("Question name mismatch")]
+    QuestionName,
+    #[error("Question type mismatch")]
+    QuestionType,
+    #[error("Question class mismatch")]
+    QuestionClass,
+}
@@ -0,0 +1,37 @@
+/// A `MinimalPolicy` describes which records are dropped by
+/// `ParsedPacket::minimize()`, similar to the `minimal-responses` option of
+/// BIND.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MinimalPolicy {
+    /// Whether the authority section is dropped from positive answers.
+    /// Referrals and negative responses always keep it.
+    pub drop_authority: bool,
+    /// Whether additional records are dropped, unless they are glue for the
+    /// targets of `NS`, `MX` and `SRV` records from the answer and authority
+    /// sections.
+    pub drop_additional: bool,
+}
+
+impl Default for MinimalPolicy {
+    fn default() -> Self {
+        MinimalPolicy::all()
+    }
+}
+
+impl MinimalPolicy {
+    /// A policy dropping both unnecessary authority and additional records.
+    pub fn all() -> Self {
+        MinimalPolicy {
+            drop_authority: true,
+            drop_additional: true,
+        }
+    }
+
+    /// A policy only dropping the authority section of positive answers.
+    pub fn no_auth() -> Self {
+        MinimalPolicy {
+            drop_authority: true,
+            drop_additional: false,
+        }
+    }
+}
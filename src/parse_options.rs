@@ -0,0 +1,104 @@
+use crate::constants::*;
+use crate::limits::*;
+
+/// Classes accepted by the default, strict parsing policy.
+const STRICT_CLASSES: &[Class] = &[Class::IN];
+
+/// Classes accepted by the permissive parsing policy.
+const PERMISSIVE_CLASSES: &[Class] = &[Class::IN, Class::CH, Class::HS, Class::NONE, Class::ANY];
+
+/// A `ParseOptions` structure describes what `DNSSector::parse_with()`
+/// accepts, beyond the structural checks that are always performed.
+///
+/// The default policy is the strict one used by `DNSSector::parse()`: exactly
+/// one question, in the `IN` class, and no answers nor name servers in
+/// queries.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ParseOptions {
+    /// Minimum number of records in the question section.
+    pub min_questions: u16,
+    /// Maximum number of records in the question section.
+    pub max_questions: u16,
+    /// Classes accepted in the question section.
+    pub allowed_classes: &'static [Class],
+    /// Whether packets without the `QR` bit may include records in the answer
+    /// and name servers sections (NOTIFY, UPDATE).
+    pub allow_records_in_queries: bool,
+    /// Whether records cut by the end of a packet with the `TC` bit set should
+    /// be dropped instead of rejecting the packet. Section counts are then
+    /// adjusted, and the `ParsedPacket` is marked as partial.
+    pub recover_truncated: bool,
+    /// Resource limits applied while parsing and modifying the packet.
+    pub limits: Limits,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self::strict()
+    }
+}
+
+impl ParseOptions {
+    /// The policy used by `DNSSector::parse()`.
+    pub fn strict() -> Self {
+        ParseOptions {
+            min_questions: 1,
+            max_questions: 1,
+            allowed_classes: STRICT_CLASSES,
+            allow_records_in_queries: false,
+            recover_truncated: false,
+            limits: Limits::default(),
+        }
+    }
+
+    /// A policy accepting any number of questions, all the classes from
+    /// `Class`, and records in queries.
+    pub fn permissive() -> Self {
+        ParseOptions {
+            min_questions: 0,
+            max_questions: 0xffff,
+            allowed_classes: PERMISSIVE_CLASSES,
+            allow_records_in_queries: true,
+            recover_truncated: false,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Sets the range of accepted question counts.
+    pub fn with_questions(mut self, min_questions: u16, max_questions: u16) -> Self {
+        self.min_questions = min_questions;
+        self.max_questions = max_questions;
+        self
+    }
+
+    /// Sets the classes accepted in the question section.
+    pub fn with_allowed_classes(mut self, allowed_classes: &'static [Class]) -> Self {
+        self.allowed_classes = allowed_classes;
+        self
+    }
+
+    /// Allows or forbids answers and name servers in packets that are not
+    /// responses.
+    pub fn with_records_in_queries(mut self, allow_records_in_queries: bool) -> Self {
+        self.allow_records_in_queries = allow_records_in_queries;
+        self
+    }
+
+    /// Enables or disables the recovery of truncated responses.
+    pub fn with_recover_truncated(mut self, recover_truncated: bool) -> Self {
+        self.recover_truncated = recover_truncated;
+        self
+    }
+
+    /// Sets the resource limits.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Returns `true` if `class` is accepted in the question section.
+    #[inline]
+    pub fn is_class_allowed(&self, class: u16) -> bool {
+        self.allowed_classes.iter().any(|&x| u16::from(x) == class)
+    }
+}
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use byteorder::{BigEndian, ByteOrder};
 use rand::prelude::*;
 
@@ -6,16 +8,23 @@ use crate::constants::*;
 use crate::dns_sector::*;
 use crate::edns_iterator::*;
 use crate::errors::*;
+use crate::matching::*;
+use crate::minimal_responses::*;
+use crate::parse_options::*;
 use crate::question_iterator::*;
+use crate::record_view::*;
 use crate::renamer::*;
 use crate::response_iterator::*;
+use crate::response_options::*;
 use crate::rr_iterator::*;
+use crate::shuffle::*;
 use crate::synth::r#gen;
+use crate::truncation::*;
 
 /// A `ParsedPacket` structure contains information about a successfully parsed
 /// DNS packet, that allows quick access to (extended) flags and to individual
 /// sections.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ParsedPacket {
     pub packet: Option<Vec<u8>>,
     pub offset_question: Option<usize>,
@@ -30,6 +39,26 @@ pub struct ParsedPacket {
     pub maybe_compressed: bool,
     pub max_payload: usize,
     pub cached: Option<(Vec<u8>, u16, u16)>,
+    pub parse_options: ParseOptions,
+    pub dropped_records: Option<DroppedRecords>,
+    pub pending_validation: bool,
+}
+
+/// Number of records dropped from each section of a truncated response, when
+/// parsing with `ParseOptions::recover_truncated`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DroppedRecords {
+    pub answers: u16,
+    pub nameservers: u16,
+    pub additional: u16,
+}
+
+impl DroppedRecords {
+    /// Returns the total number of dropped records.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.answers as usize + self.nameservers as usize + self.additional as usize
+    }
 }
 
 impl ParsedPacket {
@@ -49,6 +78,9 @@ impl ParsedPacket {
             maybe_compressed: false,
             max_payload: DNS_MAX_UNCOMPRESSED_SIZE,
             cached: None,
+            parse_options: ParseOptions::default(),
+            dropped_records: None,
+            pending_validation: false,
         };
         let mut rng = rand::rng();
         let tid: u16 = rng.random();
@@ -81,23 +113,67 @@ impl ParsedPacket {
     }
 
     /// Iterates over the answer section.
+    ///
+    /// If the packet was lazily parsed and fails validation, `None` is
+    /// returned, and the packet stays unvalidated: `validate()` and the
+    /// `try_into_iter_*()` functions keep reporting the error.
     pub fn into_iter_answer(&mut self) -> Option<AnswerIterator<'_>> {
-        AnswerIterator::new(RRIterator::new(self, Section::Answer)).next()
+        self.try_into_iter_answer().ok()?
     }
 
-    /// Iterates over the list of name servers.
+    /// Iterates over the list of name servers. Returns `None` if a lazily
+    /// parsed packet fails validation, like `into_iter_answer()`.
     pub fn into_iter_nameservers(&mut self) -> Option<NameServersIterator<'_>> {
-        NameServersIterator::new(RRIterator::new(self, Section::NameServers)).next()
+        self.try_into_iter_nameservers().ok()?
     }
 
-    /// Iterates over the additional section - OPT RRs are skipped.
+    /// Iterates over the additional section - OPT RRs are skipped. Returns
+    /// `None` if a lazily parsed packet fails validation, like
+    /// `into_iter_answer()`.
     pub fn into_iter_additional(&mut self) -> Option<AdditionalIterator<'_>> {
-        AdditionalIterator::new(RRIterator::new(self, Section::Additional)).next()
+        self.try_into_iter_additional().ok()?
     }
 
-    /// Iterates over the additional section - OPT RRs are included.
+    /// Iterates over the additional section - OPT RRs are included. Returns
+    /// `None` if a lazily parsed packet fails validation, like
+    /// `into_iter_answer()`.
     pub fn into_iter_additional_including_opt(&mut self) -> Option<AdditionalIterator<'_>> {
-        AdditionalIterator::new(RRIterator::new(self, Section::Additional)).next_including_opt()
+        self.try_into_iter_additional_including_opt().ok()?
+    }
+
+    /// Iterates over the answer section, after validating the packet if it
+    /// was lazily parsed. Returns the validation error if it fails.
+    pub fn try_into_iter_answer(&mut self) -> Result<Option<AnswerIterator<'_>>, Error> {
+        self.validate()?;
+        Ok(AnswerIterator::new(RRIterator::new(self, Section::Answer)).next())
+    }
+
+    /// Iterates over the list of name servers, after validating the packet
+    /// if it was lazily parsed. Returns the validation error if it fails.
+    pub fn try_into_iter_nameservers(&mut self) -> Result<Option<NameServersIterator<'_>>, Error> {
+        self.validate()?;
+        Ok(NameServersIterator::new(RRIterator::new(self, Section::NameServers)).next())
+    }
+
+    /// Iterates over the additional section - OPT RRs are skipped -, after
+    /// validating the packet if it was lazily parsed. Returns the validation
+    /// error if it fails.
+    pub fn try_into_iter_additional(&mut self) -> Result<Option<AdditionalIterator<'_>>, Error> {
+        self.validate()?;
+        Ok(AdditionalIterator::new(RRIterator::new(self, Section::Additional)).next())
+    }
+
+    /// Iterates over the additional section - OPT RRs are included -, after
+    /// validating the packet if it was lazily parsed. Returns the validation
+    /// error if it fails.
+    pub fn try_into_iter_additional_including_opt(
+        &mut self,
+    ) -> Result<Option<AdditionalIterator<'_>>, Error> {
+        self.validate()?;
+        Ok(
+            AdditionalIterator::new(RRIterator::new(self, Section::Additional))
+                .next_including_opt(),
+        )
     }
 
     /// Iterates over the records from the optional edns pseudo-section.
@@ -105,6 +181,41 @@ impl ParsedPacket {
         EdnsIterator::new(RRIterator::new(self, Section::Edns)).next()
     }
 
+    /// Returns `false` if the packet was lazily parsed, and its answer, name
+    /// servers and additional sections haven't been validated yet.
+    #[inline]
+    pub fn is_validated(&self) -> bool {
+        !self.pending_validation
+    }
+
+    /// Completes the validation of a packet that was lazily parsed with
+    /// `DNSSector::parse_lazy()`. This is a no-op if the packet has already
+    /// been fully validated.
+    ///
+    /// Section iterators and functions modifying the packet call this
+    /// automatically.
+    pub fn validate(&mut self) -> Result<(), Error> {
+        if !self.pending_validation {
+            return Ok(());
+        }
+        let parsed_packet_ref = DNSSector::new(self.packet())?.parse_with(self.parse_options)?;
+        let offset_answers = parsed_packet_ref.offset_answers;
+        let offset_nameservers = parsed_packet_ref.offset_nameservers;
+        let offset_additional = parsed_packet_ref.offset_additional;
+        let dropped_records = parsed_packet_ref.dropped_records;
+        debug_assert_eq!(self.offset_question, parsed_packet_ref.offset_question);
+        debug_assert_eq!(self.offset_edns, parsed_packet_ref.offset_edns);
+        if let Cow::Owned(packet) = parsed_packet_ref.packet {
+            self.packet = Some(packet);
+        }
+        self.offset_answers = offset_answers;
+        self.offset_nameservers = offset_nameservers;
+        self.offset_additional = offset_additional;
+        self.dropped_records = dropped_records;
+        self.pending_validation = false;
+        Ok(())
+    }
+
     /// Copy the packet header
     pub fn copy_header(&self, header: &mut Vec<u8>) {
         header.extend(&self.packet()[..DNS_HEADER_SIZE]);
@@ -220,6 +331,211 @@ impl ParsedPacket {
         *p |= (opcode << 3) & 0x78;
     }
 
+    /// Returns the opcode, as an `Opcode`.
+    #[inline]
+    pub fn typed_opcode(&self) -> Opcode {
+        Opcode::from(self.opcode())
+    }
+
+    /// Changes the operation code, from an `Opcode`.
+    #[inline]
+    pub fn set_typed_opcode(&mut self, opcode: Opcode) {
+        self.set_opcode(opcode.into())
+    }
+
+    /// Returns the 12-bit extended return code: the upper 8 bits are read
+    /// from the `OPT` record, if there is one, and the lower 4 bits from the
+    /// header.
+    pub fn extended_rcode(&self) -> u16 {
+        ((self.ext_rcode.unwrap_or(0) as u16) << 4) | (self.rcode() as u16)
+    }
+
+    /// Changes the 12-bit extended return code, updating both the header and
+    /// the `OPT` record.
+    /// Return codes above 15 require an `OPT` record.
+    pub fn set_extended_rcode(&mut self, rcode: u16) -> Result<(), Error> {
+        if rcode > 0x0fff {
+            bail!(DSError::InvalidPacket("Extended return code out of range"));
+        }
+        let ext_rcode = (rcode >> 4) as u8;
+        match self.offset_opt_rr() {
+            Some(offset) => {
+                self.packet_mut()[offset + DNS_OPT_RR_EXT_RCODE_OFFSET] = ext_rcode;
+                self.ext_rcode = Some(ext_rcode);
+            }
+            None if ext_rcode != 0 => bail!(DSError::PropertyNotFound),
+            None => {}
+        }
+        self.set_rcode((rcode & 0x0f) as u8);
+        Ok(())
+    }
+
+    /// Returns the extended return code, as an `Rcode`.
+    #[inline]
+    pub fn typed_rcode(&self) -> Rcode {
+        Rcode::from(self.extended_rcode())
+    }
+
+    /// Changes the extended return code, from an `Rcode`.
+    #[inline]
+    pub fn set_typed_rcode(&mut self, rcode: Rcode) -> Result<(), Error> {
+        self.set_extended_rcode(rcode.into())
+    }
+
+    /// Returns `true` if `flag` is set.
+    #[inline]
+    pub fn flag(&self, flag: Flag) -> bool {
+        self.flags() & flag.mask() != 0
+    }
+
+    /// Sets or clears `flag`.
+    /// Setting the `DO` flag requires an `OPT` record.
+    pub fn set_flag(&mut self, flag: Flag, value: bool) -> Result<(), Error> {
+        if !flag.is_extended() {
+            let mask = flag.mask() as u16;
+            let mut v = BigEndian::read_u16(&self.packet()[DNS_FLAGS_OFFSET..]);
+            if value {
+                v |= mask
+            } else {
+                v &= !mask
+            }
+            BigEndian::write_u16(&mut self.packet_mut()[DNS_FLAGS_OFFSET..], v);
+            return Ok(());
+        }
+        let offset = match self.offset_opt_rr() {
+            None if value => bail!(DSError::PropertyNotFound),
+            None => return Ok(()),
+            Some(offset) => offset + DNS_OPT_RR_EDNS_EXT_FLAGS_OFFSET,
+        };
+        let mask = (flag.mask() >> 16) as u16;
+        let mut v = BigEndian::read_u16(&self.packet()[offset..]);
+        if value {
+            v |= mask
+        } else {
+            v &= !mask
+        }
+        BigEndian::write_u16(&mut self.packet_mut()[offset..], v);
+        self.ext_flags = Some(v);
+        Ok(())
+    }
+
+    /// Sets or clears a flag stored in the header, that cannot fail.
+    #[inline]
+    fn set_header_flag(&mut self, flag: Flag, value: bool) {
+        debug_assert!(!flag.is_extended());
+        let _ = self.set_flag(flag, value);
+    }
+
+    /// Returns `true` if the `AA` (authoritative answer) flag is set.
+    #[inline]
+    pub fn aa(&self) -> bool {
+        self.flag(Flag::AA)
+    }
+
+    /// Sets or clears the `AA` (authoritative answer) flag.
+    #[inline]
+    pub fn set_aa(&mut self, value: bool) {
+        self.set_header_flag(Flag::AA, value)
+    }
+
+    /// Returns `true` if the `TC` (truncated) flag is set.
+    #[inline]
+    pub fn tc(&self) -> bool {
+        self.flag(Flag::TC)
+    }
+
+    /// Sets or clears the `TC` (truncated) flag.
+    #[inline]
+    pub fn set_tc(&mut self, value: bool) {
+        self.set_header_flag(Flag::TC, value)
+    }
+
+    /// Returns `true` if the `RD` (recursion desired) flag is set.
+    #[inline]
+    pub fn rd(&self) -> bool {
+        self.flag(Flag::RD)
+    }
+
+    /// Sets or clears the `RD` (recursion desired) flag.
+    #[inline]
+    pub fn set_rd(&mut self, value: bool) {
+        self.set_header_flag(Flag::RD, value)
+    }
+
+    /// Returns `true` if the `RA` (recursion available) flag is set.
+    #[inline]
+    pub fn ra(&self) -> bool {
+        self.flag(Flag::RA)
+    }
+
+    /// Sets or clears the `RA` (recursion available) flag.
+    #[inline]
+    pub fn set_ra(&mut self, value: bool) {
+        self.set_header_flag(Flag::RA, value)
+    }
+
+    /// Returns `true` if the reserved `Z` flag is set.
+    #[inline]
+    pub fn z(&self) -> bool {
+        self.flag(Flag::Z)
+    }
+
+    /// Sets or clears the reserved `Z` flag.
+    #[inline]
+    pub fn set_z(&mut self, value: bool) {
+        self.set_header_flag(Flag::Z, value)
+    }
+
+    /// Returns `true` if the `AD` (authentic data) flag is set.
+    #[inline]
+    pub fn ad(&self) -> bool {
+        self.flag(Flag::AD)
+    }
+
+    /// Sets or clears the `AD` (authentic data) flag.
+    #[inline]
+    pub fn set_ad(&mut self, value: bool) {
+        self.set_header_flag(Flag::AD, value)
+    }
+
+    /// Returns `true` if the `CD` (checking disabled) flag is set.
+    #[inline]
+    pub fn cd(&self) -> bool {
+        self.flag(Flag::CD)
+    }
+
+    /// Sets or clears the `CD` (checking disabled) flag.
+    #[inline]
+    pub fn set_cd(&mut self, value: bool) {
+        self.set_header_flag(Flag::CD, value)
+    }
+
+    /// Returns `true` if the `DO` (DNSSEC OK) flag is set in the `OPT` record.
+    #[inline]
+    pub fn dnssec_ok(&self) -> bool {
+        self.flag(Flag::DO)
+    }
+
+    /// Sets or clears the `DO` (DNSSEC OK) flag. Setting it requires an `OPT`
+    /// record.
+    #[inline]
+    pub fn set_dnssec_ok(&mut self, value: bool) -> Result<(), Error> {
+        self.set_flag(Flag::DO, value)
+    }
+
+    /// Returns the location of the `OPT` record, right after its name.
+    fn offset_opt_rr(&self) -> Option<usize> {
+        let offset_edns = self.offset_edns?;
+        debug_assert!(offset_edns >= DNS_OPT_RR_HEADER_SIZE);
+        Some(offset_edns - DNS_OPT_RR_HEADER_SIZE)
+    }
+
+    /// Returns `true` if records had to be dropped from a truncated response.
+    #[inline]
+    pub fn is_partial(&self) -> bool {
+        self.dropped_records.is_some()
+    }
+
     /// Maximum payload size when using UDP
     #[inline]
     pub fn max_payload(&self) -> usize {
@@ -228,6 +544,7 @@ impl ParsedPacket {
 
     /// Increments the number of records in a given section
     pub fn rrcount_inc(&mut self, section: Section) -> Result<u16, Error> {
+        self.validate()?;
         let packet = &mut self.packet_mut();
         let mut rrcount = match section {
             Section::Question => {
@@ -262,6 +579,7 @@ impl ParsedPacket {
 
     /// Decrements the number of records in a given section
     pub fn rrcount_dec(&mut self, section: Section) -> Result<u16, Error> {
+        self.validate()?;
         let packet = &mut self.packet_mut();
         let mut rrcount = match section {
             Section::Question => DNSSector::qdcount(packet),
@@ -309,16 +627,21 @@ impl ParsedPacket {
     }
 
     pub fn insert_rr(&mut self, section: Section, rr: r#gen::RR) -> Result<(), Error> {
+        self.validate()?;
         if self.maybe_compressed {
-            let uncompressed = Compress::uncompress(self.packet())?;
+            let uncompressed =
+                Compress::uncompress_with_options(self.packet(), self.parse_options)?;
             self.packet = Some(uncompressed);
             self.recompute()?;
             debug_assert!(!self.maybe_compressed);
         }
         let rr_len = rr.packet.len();
-        if DNS_MAX_UNCOMPRESSED_SIZE - self.packet().len() < rr_len {
+        if self.packet().len() + rr_len > DNS_MAX_PACKET_SIZE {
             bail!(DSError::PacketTooLarge)
         }
+        if self.packet().len() + rr_len > self.parse_options.limits.max_packet_size {
+            bail!(DSError::LimitExceeded("Packet too large"))
+        }
         let insertion_offset = self.insertion_offset(section)?;
         let packet_len = self.packet().len();
         let new_len = packet_len + rr_len;
@@ -376,7 +699,7 @@ impl ParsedPacket {
             return Ok(());
         }
         let dns_sector = DNSSector::new(self.packet.take().expect("self.packet is None"))?;
-        let parsed_packet = dns_sector.parse()?;
+        let parsed_packet = dns_sector.parse_with(self.parse_options)?;
         self.offset_question = parsed_packet.offset_question;
         self.offset_answers = parsed_packet.offset_answers;
         self.offset_nameservers = parsed_packet.offset_nameservers;
@@ -387,6 +710,7 @@ impl ParsedPacket {
         assert_eq!(self.edns_version, parsed_packet.edns_version);
         assert_eq!(self.ext_flags, parsed_packet.ext_flags);
         self.maybe_compressed = false;
+        self.pending_validation = false;
         self.packet = Some(parsed_packet.into_packet());
         self.cached = None;
         Ok(())
@@ -395,8 +719,18 @@ impl ParsedPacket {
     /// Returns the question as a raw vector, without case conversion, as well
     /// as the query type and class Names include a trailing `0`
     pub fn question_raw0(&mut self) -> Option<(&[u8], u16, u16)> {
+        if self.cached.is_none() {
+            self.cached = Some(self.uncached_question_raw0()?);
+        }
+        let cached = self.cached.as_ref().unwrap();
+        Some((&cached.0, cached.1, cached.2))
+    }
+
+    /// Returns a copy of the question, like `question_raw0()`, without
+    /// updating the cache.
+    fn uncached_question_raw0(&self) -> Option<(Vec<u8>, u16, u16)> {
         if let Some(ref cached) = self.cached {
-            return Some((&cached.0, cached.1, cached.2));
+            return Some(cached.clone());
         }
         let offset = self.offset_question?;
         let mut name = Vec::with_capacity(DNS_MAX_HOSTNAME_LEN);
@@ -409,9 +743,7 @@ impl ParsedPacket {
             let rr_class = BigEndian::read_u16(&rdata[DNS_RR_CLASS_OFFSET..]);
             (rr_type, rr_class)
         };
-        self.cached = Some((name, rr_type, rr_class));
-        let cached = self.cached.as_ref().unwrap();
-        Some((&cached.0, cached.1, cached.2))
+        Some((name, rr_type, rr_class))
     }
 
     /// Returns the question as a raw vector, without case conversion, as well
@@ -458,6 +790,965 @@ impl ParsedPacket {
         Some((rr_type, rr_class))
     }
 
+    /// Builds a response to this query, with the same transaction ID, opcode
+    /// and question, and no records.
+    ///
+    /// The `RD` and `CD` flags are copied from the query, `AA` and `RA` are
+    /// set according to `options`, and other flags are cleared. The case of
+    /// the question is preserved. If the query has an `OPT` record, the
+    /// response includes an `OPT` record advertising `options.max_payload`
+    /// and echoing the `DO` flag, with `options.extended_error` as its only
+    /// option.
+    pub fn to_response(&self, options: ResponseOptions) -> Result<ParsedPacket, Error> {
+        Self::build_response(
+            self.packet().to_vec(),
+            self.offset_question,
+            self.ext_flags,
+            self.parse_options,
+            options,
+        )
+    }
+
+    /// Turns this query into a response, like `to_response()` does, reusing
+    /// its buffer.
+    pub fn into_response(mut self, options: ResponseOptions) -> Result<ParsedPacket, Error> {
+        let packet = self.packet.take().expect("self.packet is None");
+        Self::build_response(
+            packet,
+            self.offset_question,
+            self.ext_flags,
+            self.parse_options,
+            options,
+        )
+    }
+
+    /// Rewrites a query into a response, keeping only the header, the
+    /// question section and the `OPT` record.
+    fn build_response(
+        mut packet: Vec<u8>,
+        offset_question: Option<usize>,
+        ext_flags: Option<u16>,
+        parse_options: ParseOptions,
+        options: ResponseOptions,
+    ) -> Result<ParsedPacket, Error> {
+        let mut qdcount = 0;
+        let mut questions = Vec::new();
+        if let Some(mut offset) = offset_question {
+            qdcount = DNSSector::qdcount(&packet);
+            for _ in 0..qdcount {
+                offset =
+                    Compress::copy_uncompressed_name(&mut questions, &packet, offset).final_offset;
+                questions.extend_from_slice(&packet[offset..offset + DNS_RR_QUESTION_HEADER_SIZE]);
+                offset += DNS_RR_QUESTION_HEADER_SIZE;
+            }
+        }
+        let query_flags = BigEndian::read_u16(&packet[DNS_FLAGS_OFFSET..]);
+        let copied_flags = 0x7800 | DNS_FLAG_RD as u16 | DNS_FLAG_CD as u16;
+        let flags = DNS_FLAG_QR as u16 | (query_flags & copied_flags);
+        packet.truncate(DNS_HEADER_SIZE);
+        BigEndian::write_u16(&mut packet[DNS_FLAGS_OFFSET..], flags);
+        DNSSector::set_qdcount(&mut packet, qdcount);
+        DNSSector::set_ancount(&mut packet, 0);
+        DNSSector::set_nscount(&mut packet, 0);
+        DNSSector::set_arcount(&mut packet, 0);
+        packet.extend_from_slice(&questions);
+        if let Some(ext_flags) = ext_flags {
+            let mut opt = [0u8; 1 + DNS_OPT_RR_HEADER_SIZE];
+            let header = &mut opt[1..];
+            BigEndian::write_u16(&mut header[DNS_RR_TYPE_OFFSET..], Type::OPT.into());
+            BigEndian::write_u16(
+                &mut header[DNS_OPT_RR_MAX_PAYLOAD_OFFSET..],
+                options.max_payload,
+            );
+            BigEndian::write_u16(
+                &mut header[DNS_OPT_RR_EDNS_EXT_FLAGS_OFFSET..],
+                ext_flags & (DNS_FLAG_DO >> 16) as u16,
+            );
+            packet.extend_from_slice(&opt);
+            DNSSector::set_arcount(&mut packet, 1);
+        }
+        let mut response = DNSSector::new(packet)?.parse_with(parse_options)?;
+        response.set_typed_rcode(options.rcode)?;
+        response.set_aa(options.authoritative);
+        response.set_ra(options.recursion_available);
+        if let (Some(extended_error), Some(_)) = (options.extended_error, response.offset_edns) {
+            response.add_extended_error(extended_error)?;
+        }
+        Ok(response)
+    }
+
+    /// Builds an NXDOMAIN response to this query, like `to_response()` does,
+    /// with `soa` in the authority section (RFC 2308). The TTL of `soa` is
+    /// capped by its `MINIMUM` field. The return code from `options` is
+    /// ignored.
+    pub fn to_nxdomain(
+        &self,
+        soa: r#gen::RR,
+        options: ResponseOptions,
+    ) -> Result<ParsedPacket, Error> {
+        self.to_negative_response(soa, options.with_rcode(Rcode::NXDOMAIN))
+    }
+
+    /// Builds a NODATA response to this query, like `to_nxdomain()` does,
+    /// but with a `NOERROR` return code.
+    pub fn to_nodata(
+        &self,
+        soa: r#gen::RR,
+        options: ResponseOptions,
+    ) -> Result<ParsedPacket, Error> {
+        self.to_negative_response(soa, options.with_rcode(Rcode::NOERROR))
+    }
+
+    fn to_negative_response(
+        &self,
+        mut soa: r#gen::RR,
+        options: ResponseOptions,
+    ) -> Result<ParsedPacket, Error> {
+        if soa.rr_type() != Type::SOA.into() {
+            bail!(DSError::InvalidPacket(
+                "Negative responses require a SOA record"
+            ));
+        }
+        let rdata = soa.rdata();
+        if rdata.len() < DNS_SOA_RR_FIXED_SIZE {
+            bail!(DSError::InvalidPacket("SOA record is too short"));
+        }
+        let minimum = BigEndian::read_u32(&rdata[rdata.len() - 4..]);
+        let ttl = soa.ttl().unwrap_or(0).min(minimum);
+        soa.set_ttl(ttl);
+        let mut response = self.to_response(options)?;
+        response.insert_rr(Section::NameServers, soa)?;
+        Ok(response)
+    }
+
+    /// Appends an option to the `OPT` record.
+    pub fn add_edns_option(&mut self, code: u16, data: &[u8]) -> Result<(), Error> {
+        self.validate()?;
+        let offset_opt = match self.offset_opt_rr() {
+            None => bail!(DSError::PropertyNotFound),
+            Some(offset_opt) => offset_opt,
+        };
+        if self.edns_count >= self.parse_options.limits.max_edns_options {
+            bail!(DSError::LimitExceeded("Too many edns options"));
+        }
+        let rdlen = BigEndian::read_u16(&self.packet()[offset_opt + DNS_OPT_RR_RDLEN_OFFSET..]);
+        let option_len = DNS_EDNS_RR_HEADER_SIZE + data.len();
+        if rdlen as usize + option_len > 0xffff {
+            bail!(DSError::InvalidPacket("EDNS option too large"));
+        }
+        if self.packet().len() + option_len > self.parse_options.limits.max_packet_size {
+            bail!(DSError::LimitExceeded("Packet too large"))
+        }
+        let mut option = vec![0u8; option_len];
+        BigEndian::write_u16(&mut option[DNS_EDNS_RR_CODE_OFFSET..], code);
+        BigEndian::write_u16(&mut option[DNS_EDNS_RR_RDLEN_OFFSET..], data.len() as u16);
+        option[DNS_EDNS_RR_HEADER_SIZE..].copy_from_slice(data);
+        let insertion_offset = offset_opt + DNS_OPT_RR_HEADER_SIZE + rdlen as usize;
+        let packet = self.packet_mut();
+        packet.splice(insertion_offset..insertion_offset, option);
+        BigEndian::write_u16(
+            &mut packet[offset_opt + DNS_OPT_RR_RDLEN_OFFSET..],
+            rdlen + option_len as u16,
+        );
+        self.edns_count += 1;
+        Ok(())
+    }
+
+    /// Adds an Extended DNS Error (RFC 8914) to the `OPT` record.
+    pub fn add_extended_error(&mut self, extended_error: ExtendedError) -> Result<(), Error> {
+        let mut info_code = [0u8; 2];
+        BigEndian::write_u16(&mut info_code, extended_error.into());
+        self.add_edns_option(EdnsOption::EXTENDED_ERROR.into(), &info_code)
+    }
+
+    /// Drops records until the packet is at most `max_len` bytes long.
+    ///
+    /// Records are dropped from the end of the additional section first, then
+    /// from the name servers and answer sections, so that compression
+    /// pointers in the remaining records stay valid. The `OPT` record is
+    /// always kept. The `TC` flag is set if answers had to be dropped.
+    ///
+    /// Returns the number of records dropped from each section. An error is
+    /// returned, and the packet is left untouched, if it doesn't fit even
+    /// without any records.
+    pub fn truncate_to(
+        &mut self,
+        max_len: usize,
+        policy: TruncationPolicy,
+    ) -> Result<DroppedRecords, Error> {
+        self.validate()?;
+        let mut dropped_records = DroppedRecords::default();
+        if self.packet().len() <= max_len {
+            return Ok(dropped_records);
+        }
+        let (rrs, opt) = self.record_locations();
+
+        // Find how many records can be kept. `kept_len(n)` is the length of
+        // the packet when only the first `n` records are kept, along with the
+        // OPT record.
+        let opt_len = opt.map_or(0, |(start, end)| end - start);
+        let kept_len = |kept: usize| {
+            let cut = rrs[kept].1;
+            match opt {
+                Some((opt_start, _)) if opt_start > cut => cut + opt_len,
+                _ => cut,
+            }
+        };
+        let mut kept = rrs.len();
+        loop {
+            if kept == 0 {
+                bail!(DSError::PacketTooLarge);
+            }
+            kept -= 1;
+            if kept_len(kept) <= max_len {
+                break;
+            }
+        }
+        if policy.keep_rrsets {
+            while kept > 0 && self.is_same_rrset(rrs[kept - 1], rrs[kept]) {
+                kept -= 1;
+            }
+        }
+
+        for &(section, _, _) in &rrs[kept..] {
+            match section {
+                Section::Answer => dropped_records.answers += 1,
+                Section::NameServers => dropped_records.nameservers += 1,
+                _ => dropped_records.additional += 1,
+            }
+        }
+        let cut = rrs[kept].1;
+        let offset_opt = match opt {
+            Some((opt_start, opt_end)) if opt_start > cut => {
+                let packet = self.packet_mut();
+                packet.copy_within(opt_start..opt_end, cut);
+                packet.truncate(cut + opt_len);
+                Some((opt_start, cut))
+            }
+            _ => {
+                self.packet_mut().truncate(cut);
+                None
+            }
+        };
+        let packet = self.packet_mut();
+        let ancount = DNSSector::ancount(packet) - dropped_records.answers;
+        let nscount = DNSSector::nscount(packet) - dropped_records.nameservers;
+        let arcount = DNSSector::arcount(packet) - dropped_records.additional;
+        DNSSector::set_ancount(packet, ancount);
+        DNSSector::set_nscount(packet, nscount);
+        DNSSector::set_arcount(packet, arcount);
+        if ancount == 0 {
+            self.offset_answers = None;
+        }
+        if nscount == 0 {
+            self.offset_nameservers = None;
+        }
+        if arcount == 0 {
+            self.offset_additional = None;
+        }
+        if let Some((opt_start, new_opt_start)) = offset_opt {
+            self.offset_edns = self.offset_edns.map(|x| x - opt_start + new_opt_start);
+            if arcount == 1 {
+                self.offset_additional = Some(new_opt_start);
+            }
+        }
+        if dropped_records.answers > 0 {
+            self.set_tc(true);
+        }
+        Ok(dropped_records)
+    }
+
+    /// Clamps the TTL of every record to `[min, max]`. If `min` is larger
+    /// than `max`, `max` wins. The `OPT` record is left untouched.
+    pub fn clamp_ttls(&mut self, min: u32, max: u32) -> Result<(), Error> {
+        self.validate()?;
+        for offset in self.ttl_offsets() {
+            let packet = self.packet_mut();
+            let ttl = BigEndian::read_u32(&packet[offset..]);
+            BigEndian::write_u32(&mut packet[offset..], ttl.max(min).min(max));
+        }
+        Ok(())
+    }
+
+    /// Subtracts `elapsed` seconds from the TTL of every record, down to `0`.
+    /// The `OPT` record is left untouched.
+    ///
+    /// Returns `true` if at least one record expired.
+    pub fn decrement_ttls(&mut self, elapsed: u32) -> Result<bool, Error> {
+        self.validate()?;
+        let mut expired = false;
+        for offset in self.ttl_offsets() {
+            let packet = self.packet_mut();
+            let ttl = BigEndian::read_u32(&packet[offset..]);
+            expired |= ttl <= elapsed;
+            BigEndian::write_u32(&mut packet[offset..], ttl.saturating_sub(elapsed));
+        }
+        Ok(expired)
+    }
+
+    /// Prepares a response to be served after it expired (RFC 8767): the TTL
+    /// of every record is set to `stale_ttl`, and if the packet has an `OPT`
+    /// record, a "Stale Answer" or "Stale NXDOMAIN Answer" Extended DNS Error
+    /// is added.
+    pub fn mark_stale(&mut self, stale_ttl: u32) -> Result<(), Error> {
+        self.clamp_ttls(stale_ttl, stale_ttl)?;
+        if self.offset_edns.is_some() {
+            let extended_error = if self.typed_rcode() == Rcode::NXDOMAIN {
+                ExtendedError::STALE_NXDOMAIN_ANSWER
+            } else {
+                ExtendedError::STALE_ANSWER
+            };
+            self.add_extended_error(extended_error)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the lowest TTL of all the records, ignoring the `OPT` record,
+    /// or `None` if there are no records.
+    ///
+    /// The TTL of a SOA record from the name servers section is capped by its
+    /// `MINIMUM` field, as it is for negative caching (RFC 2308 section 5).
+    pub fn min_ttl(&mut self) -> Result<Option<u32>, Error> {
+        self.validate()?;
+        let packet = self.packet();
+        let (rrs, _) = self.record_locations();
+        let min_ttl = rrs
+            .into_iter()
+            .map(|(section, offset, offset_next)| {
+                let name_end = RRIterator::skip_name(packet, offset);
+                let ttl = BigEndian::read_u32(&packet[name_end + DNS_RR_TTL_OFFSET..]);
+                let rr_type = BigEndian::read_u16(&packet[name_end + DNS_RR_TYPE_OFFSET..]);
+                if section == Section::NameServers
+                    && rr_type == Type::SOA.into()
+                    && offset_next - name_end >= DNS_RR_HEADER_SIZE + DNS_SOA_RR_FIXED_SIZE
+                {
+                    ttl.min(BigEndian::read_u32(&packet[offset_next - 4..]))
+                } else {
+                    ttl
+                }
+            })
+            .min();
+        Ok(min_ttl)
+    }
+
+    /// Returns the location of the TTL of every record, except the `OPT`
+    /// record.
+    fn ttl_offsets(&self) -> Vec<usize> {
+        let packet = self.packet();
+        let (rrs, _) = self.record_locations();
+        rrs.into_iter()
+            .map(|(_, offset, _)| RRIterator::skip_name(packet, offset) + DNS_RR_TTL_OFFSET)
+            .collect()
+    }
+
+    /// Removes the records of the answer, name servers and additional
+    /// sections for which `f` returns `false`. The `OPT` record is always
+    /// kept.
+    ///
+    /// Records are removed in a single pass. Compressed packets are only
+    /// uncompressed if a kept record follows a removed one, since it may
+    /// point to a name from the removed record.
+    ///
+    /// Returns the number of records removed from each section.
+    pub fn retain<F>(&mut self, f: F) -> Result<DroppedRecords, Error>
+    where
+        F: FnMut(&RecordView<'_>) -> bool,
+    {
+        self.retain_sections(
+            &[Section::Answer, Section::NameServers, Section::Additional],
+            f,
+        )
+    }
+
+    /// Removes the records of the answer section for which `f` returns
+    /// `false`, like `retain()` does.
+    pub fn retain_answers<F>(&mut self, f: F) -> Result<DroppedRecords, Error>
+    where
+        F: FnMut(&RecordView<'_>) -> bool,
+    {
+        self.retain_sections(&[Section::Answer], f)
+    }
+
+    /// Removes the records of the name servers section for which `f` returns
+    /// `false`, like `retain()` does.
+    pub fn retain_nameservers<F>(&mut self, f: F) -> Result<DroppedRecords, Error>
+    where
+        F: FnMut(&RecordView<'_>) -> bool,
+    {
+        self.retain_sections(&[Section::NameServers], f)
+    }
+
+    /// Removes the records of the additional section for which `f` returns
+    /// `false`, like `retain()` does. The `OPT` record is always kept.
+    pub fn retain_additional<F>(&mut self, f: F) -> Result<DroppedRecords, Error>
+    where
+        F: FnMut(&RecordView<'_>) -> bool,
+    {
+        self.retain_sections(&[Section::Additional], f)
+    }
+
+    /// Removes DNSSEC records a client didn't ask for, and clears the `AD`
+    /// flag.
+    ///
+    /// `RRSIG`, `NSEC` and `NSEC3` records are removed from all sections.
+    /// `DS` and `DNSKEY` records are only kept if their type is the query
+    /// type.
+    ///
+    /// Returns the number of records removed from each section.
+    pub fn strip_dnssec(&mut self) -> Result<DroppedRecords, Error> {
+        let qtype = self.qtype_qclass().map(|(qtype, _)| qtype);
+        let dropped_records = self.retain(|record_view| {
+            let rr_type = record_view.rr_type();
+            match rr_type {
+                x if x == Type::RRSIG.into()
+                    || x == Type::NSEC.into()
+                    || x == Type::NSEC3.into() =>
+                {
+                    false
+                }
+                x if x == Type::DS.into() || x == Type::DNSKEY.into() => qtype == Some(rr_type),
+                _ => true,
+            }
+        })?;
+        self.set_ad(false);
+        Ok(dropped_records)
+    }
+
+    /// Removes unnecessary records from a response, as described by `policy`.
+    ///
+    /// The authority section is dropped from positive answers, but kept in
+    /// referrals and negative responses. Additional records are dropped
+    /// unless they are addresses of the targets of `NS`, `MX` and `SRV`
+    /// records from the remaining answer and authority records, or their
+    /// signatures. `OPT`, `TSIG` and `SIG(0)` records are always kept.
+    ///
+    /// Returns the number of records removed from each section.
+    pub fn minimize(&mut self, policy: MinimalPolicy) -> Result<DroppedRecords, Error> {
+        self.validate()?;
+        let (rrs, _) = self.record_locations();
+        let packet = self.packet();
+        let rr_type_at = |offset: usize| {
+            let name_end = RRIterator::skip_name(packet, offset);
+            BigEndian::read_u16(&packet[name_end + DNS_RR_TYPE_OFFSET..])
+        };
+        let positive = self.typed_rcode() == Rcode::NOERROR
+            && DNSSector::ancount(packet) > 0
+            && !rrs.iter().any(|&(section, offset, _)| {
+                section == Section::NameServers && rr_type_at(offset) == Type::SOA.into()
+            });
+        let drop_authority = policy.drop_authority && positive;
+        let mut targets = vec![];
+        if policy.drop_additional {
+            for &(section, offset, _) in &rrs {
+                if section == Section::Additional
+                    || (section == Section::NameServers && drop_authority)
+                {
+                    continue;
+                }
+                let name_end = RRIterator::skip_name(packet, offset);
+                let offset_rdata = name_end + DNS_RR_HEADER_SIZE;
+                let offset_target = match rr_type_at(offset) {
+                    x if x == Type::NS.into() => offset_rdata,
+                    x if x == Type::MX.into() => offset_rdata + 2,
+                    x if x == Type::SRV.into() => offset_rdata + 6,
+                    _ => continue,
+                };
+                let mut target = Compress::raw_name_to_str(packet, offset_target);
+                target.make_ascii_lowercase();
+                targets.push(target);
+            }
+        }
+        self.retain(|record_view| match record_view.section {
+            Section::NameServers => !drop_authority,
+            Section::Additional if policy.drop_additional => match record_view.rr_type() {
+                x if x == Type::TSIG.into() || x == Type::SIG.into() => true,
+                x if x == Type::A.into() || x == Type::AAAA.into() || x == Type::RRSIG.into() => {
+                    targets.contains(&record_view.name())
+                }
+                _ => false,
+            },
+            _ => true,
+        })
+    }
+
+    fn retain_sections<F>(
+        &mut self,
+        sections: &[Section],
+        mut f: F,
+    ) -> Result<DroppedRecords, Error>
+    where
+        F: FnMut(&RecordView<'_>) -> bool,
+    {
+        self.validate()?;
+        let mut dropped_records = DroppedRecords::default();
+        let (rrs, _) = self.record_locations();
+        let packet = self.packet();
+        let keep: Vec<bool> = rrs
+            .iter()
+            .map(|&(section, offset, _)| {
+                !sections.contains(&section)
+                    || f(&RecordView {
+                        packet,
+                        section,
+                        offset,
+                        name_end: RRIterator::skip_name(packet, offset),
+                    })
+            })
+            .collect();
+        let first_removed = match keep.iter().position(|&keep| !keep) {
+            None => return Ok(dropped_records),
+            Some(first_removed) => first_removed,
+        };
+        if self.maybe_compressed && keep[first_removed..].iter().any(|&keep| keep) {
+            let uncompressed =
+                Compress::uncompress_with_options(self.packet(), self.parse_options)?;
+            self.packet = Some(uncompressed);
+            self.recompute()?;
+        }
+
+        // Rebuild the sections with the kept records, including the OPT record
+        let (rrs, opt) = self.record_locations();
+        let mut records: Vec<_> = rrs
+            .into_iter()
+            .zip(keep)
+            .map(|((section, start, end), keep)| (section, start, end, keep))
+            .collect();
+        if let Some((start, end)) = opt {
+            let position = records.partition_point(|&(_, offset, _, _)| offset < start);
+            records.insert(position, (Section::Additional, start, end, true));
+        }
+        let packet = self.packet();
+        let records_start = records[0].1;
+        let mut new_packet = Vec::with_capacity(packet.len());
+        new_packet.extend_from_slice(&packet[..records_start]);
+        let (mut ancount, mut nscount, mut arcount) = (0, 0, 0);
+        let (mut offset_answers, mut offset_nameservers, mut offset_additional) =
+            (None, None, None);
+        let mut offset_edns = self.offset_edns;
+        for (section, start, end, keep) in records {
+            let (rrcount, offset_section, dropped) = match section {
+                Section::Answer => (
+                    &mut ancount,
+                    &mut offset_answers,
+                    &mut dropped_records.answers,
+                ),
+                Section::NameServers => (
+                    &mut nscount,
+                    &mut offset_nameservers,
+                    &mut dropped_records.nameservers,
+                ),
+                _ => (
+                    &mut arcount,
+                    &mut offset_additional,
+                    &mut dropped_records.additional,
+                ),
+            };
+            if !keep {
+                *dropped += 1;
+                continue;
+            }
+            let new_start = new_packet.len();
+            if matches!(opt, Some((opt_start, _)) if opt_start == start) {
+                offset_edns = offset_edns.map(|x| x - start + new_start);
+            }
+            *offset_section = offset_section.or(Some(new_start));
+            *rrcount += 1;
+            new_packet.extend_from_slice(&packet[start..end]);
+        }
+        DNSSector::set_ancount(&mut new_packet, ancount);
+        DNSSector::set_nscount(&mut new_packet, nscount);
+        DNSSector::set_arcount(&mut new_packet, arcount);
+        self.packet = Some(new_packet);
+        self.offset_answers = offset_answers;
+        self.offset_nameservers = offset_nameservers;
+        self.offset_additional = offset_additional;
+        self.offset_edns = offset_edns;
+        Ok(dropped_records)
+    }
+
+    /// Returns the location of the records from the answer, name servers and
+    /// additional sections as `(section, start, end)`, along with the
+    /// location of the `OPT` record, that is not included in the list.
+    fn record_locations(&self) -> (Vec<(Section, usize, usize)>, Option<(usize, usize)>) {
+        let packet = self.packet();
+        let mut rrs = vec![];
+        let mut opt = None;
+        for (section, offset, count) in [
+            (
+                Section::Answer,
+                self.offset_answers,
+                DNSSector::ancount(packet),
+            ),
+            (
+                Section::NameServers,
+                self.offset_nameservers,
+                DNSSector::nscount(packet),
+            ),
+            (
+                Section::Additional,
+                self.offset_additional,
+                DNSSector::arcount(packet),
+            ),
+        ] {
+            let mut offset = match offset {
+                None => continue,
+                Some(offset) => offset,
+            };
+            for _ in 0..count {
+                let offset_next = RRIterator::skip_rr(packet, offset);
+                let name_end = RRIterator::skip_name(packet, offset);
+                let rr_type = BigEndian::read_u16(&packet[name_end + DNS_RR_TYPE_OFFSET..]);
+                if section == Section::Additional && rr_type == Type::OPT.into() {
+                    opt = Some((offset, offset_next));
+                } else {
+                    rrs.push((section, offset, offset_next));
+                }
+                offset = offset_next;
+            }
+        }
+        (rrs, opt)
+    }
+
+    /// Reorders the records of every RRset from `section` according to
+    /// `strategy`.
+    ///
+    /// RRsets are runs of adjacent records with the same name, type and
+    /// class. Records are never moved from one RRset to another, so that
+    /// the order of the RRsets themselves, such as a `CNAME` chain, is
+    /// preserved. Compressed packets are uncompressed if records have to be
+    /// moved.
+    pub fn shuffle_rrsets(
+        &mut self,
+        section: Section,
+        strategy: ShuffleStrategy,
+    ) -> Result<(), Error> {
+        self.validate()?;
+        let (rrs, _) = self.record_locations();
+        let rrs: Vec<_> = rrs.into_iter().filter(|rr| rr.0 == section).collect();
+        let mut rrsets: Vec<(usize, Vec<usize>)> = vec![];
+        let mut start = 0;
+        for i in 1..=rrs.len() {
+            if i < rrs.len() && rrs[i - 1].2 == rrs[i].1 && self.is_same_rrset(rrs[i - 1], rrs[i]) {
+                continue;
+            }
+            if i - start > 1 {
+                let order = self.rrset_order(&rrs[start..i], strategy);
+                if order.iter().enumerate().any(|(j, &k)| j != k) {
+                    rrsets.push((start, order));
+                }
+            }
+            start = i;
+        }
+        if rrsets.is_empty() {
+            return Ok(());
+        }
+        if self.maybe_compressed {
+            let uncompressed =
+                Compress::uncompress_with_options(self.packet(), self.parse_options)?;
+            self.packet = Some(uncompressed);
+            self.recompute()?;
+        }
+
+        // Records only move inside their RRset, so that offsets don't change
+        let (rrs, _) = self.record_locations();
+        let rrs: Vec<_> = rrs.into_iter().filter(|rr| rr.0 == section).collect();
+        let packet = self.packet.as_mut().unwrap();
+        for (start, order) in rrsets {
+            let records: Vec<_> = order
+                .iter()
+                .map(|&i| packet[rrs[start + i].1..rrs[start + i].2].to_vec())
+                .collect();
+            let mut offset = rrs[start].1;
+            for record in records {
+                packet[offset..offset + record.len()].copy_from_slice(&record);
+                offset += record.len();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the new order of the records of an RRset, given as
+    /// `(section, start, end)`.
+    fn rrset_order(
+        &self,
+        rrset: &[(Section, usize, usize)],
+        strategy: ShuffleStrategy,
+    ) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..rrset.len()).collect();
+        match strategy {
+            ShuffleStrategy::Random => order.shuffle(&mut rand::rng()),
+            ShuffleStrategy::Rotate(counter) => {
+                order.rotate_left((counter % rrset.len() as u64) as usize)
+            }
+            ShuffleStrategy::Sortlist(client_addr) => {
+                let packet = self.packet();
+                let prefix_lens: Vec<_> = rrset
+                    .iter()
+                    .map(|&(section, offset, _)| {
+                        let record_view = RecordView {
+                            packet,
+                            section,
+                            offset,
+                            name_end: RRIterator::skip_name(packet, offset),
+                        };
+                        record_view
+                            .rr_ip()
+                            .map_or(0, |ip_addr| common_prefix_len(&ip_addr, &client_addr))
+                    })
+                    .collect();
+                order.sort_by_key(|&i| std::cmp::Reverse(prefix_lens[i]));
+            }
+        }
+        order
+    }
+
+    /// Collapses a `CNAME` chain starting from the question name into the
+    /// records it leads to, which are renamed to the question name.
+    ///
+    /// `qname CNAME a`, `a CNAME b` and `b A 1.2.3.4` become
+    /// `qname A 1.2.3.4`, with the minimum TTL of the chain. The `CNAME`
+    /// records of the chain and the `RRSIG` records of the names from the
+    /// chain are removed, since the signatures wouldn't match any more.
+    ///
+    /// Returns `false` if the answer doesn't start with a `CNAME` record for
+    /// the question name, and an error if the chain has a loop, or doesn't
+    /// lead to any record of the query type. The packet is left untouched in
+    /// both cases.
+    pub fn flatten_cname_chain(&mut self) -> Result<bool, Error> {
+        self.validate()?;
+        let (qname, qtype) = match self.question_raw0() {
+            None => return Ok(false),
+            Some((qname, qtype, _)) => (qname.to_vec(), qtype),
+        };
+        if qtype == Type::CNAME.into() || qtype == Type::ANY.into() {
+            return Ok(false);
+        }
+        let (rrs, _) = self.record_locations();
+        let packet = self.packet();
+        let answers: Vec<_> = rrs
+            .iter()
+            .filter(|rr| rr.0 == Section::Answer)
+            .map(|&(_, offset, _)| {
+                let mut name = Vec::with_capacity(DNS_MAX_HOSTNAME_LEN);
+                let name_end =
+                    Compress::copy_uncompressed_name(&mut name, packet, offset).final_offset;
+                let rr_type = BigEndian::read_u16(&packet[name_end + DNS_RR_TYPE_OFFSET..]);
+                let rr_ttl = BigEndian::read_u32(&packet[name_end + DNS_RR_TTL_OFFSET..]);
+                (name, rr_type, rr_ttl, name_end)
+            })
+            .collect();
+
+        // Follow the chain
+        let mut chain_names = vec![qname.clone()];
+        let mut chain = vec![];
+        let mut ttl = u32::MAX;
+        while let Some(i) = answers.iter().position(|(name, rr_type, ..)| {
+            *rr_type == Type::CNAME.into() && name.eq_ignore_ascii_case(chain_names.last().unwrap())
+        }) {
+            let mut target = Vec::with_capacity(DNS_MAX_HOSTNAME_LEN);
+            Compress::copy_uncompressed_name(
+                &mut target,
+                packet,
+                answers[i].3 + DNS_RR_HEADER_SIZE,
+            );
+            if chain_names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&target))
+            {
+                bail!(DSError::InvalidPacket("CNAME chain loop"));
+            }
+            chain.push(i);
+            ttl = ttl.min(answers[i].2);
+            chain_names.push(target);
+        }
+        if chain.is_empty() {
+            return Ok(false);
+        }
+        let final_name = chain_names.last().unwrap();
+        let final_rrset: Vec<_> = (0..answers.len())
+            .filter(|&i| answers[i].1 == qtype && answers[i].0.eq_ignore_ascii_case(final_name))
+            .collect();
+        if final_rrset.is_empty() {
+            bail!(DSError::InvalidPacket("Dangling CNAME chain"));
+        }
+        for &i in &final_rrset {
+            ttl = ttl.min(answers[i].2);
+        }
+        let renamed_names = final_rrset
+            .iter()
+            .map(|&i| Renamer::replace_raw(&answers[i].0, &qname, final_name, false))
+            .collect::<Result<Vec<_>, _>>()?;
+        let removed: Vec<_> = (0..answers.len())
+            .map(|i| {
+                chain.contains(&i)
+                    || (answers[i].1 == Type::RRSIG.into()
+                        && chain_names
+                            .iter()
+                            .any(|name| name.eq_ignore_ascii_case(&answers[i].0)))
+            })
+            .collect();
+
+        // Names of other records may point to the removed records
+        if self.maybe_compressed {
+            let uncompressed =
+                Compress::uncompress_with_options(self.packet(), self.parse_options)?;
+            self.packet = Some(uncompressed);
+            self.recompute()?;
+        }
+        let (rrs, _) = self.record_locations();
+        let answers: Vec<_> = rrs
+            .into_iter()
+            .filter(|rr| rr.0 == Section::Answer)
+            .collect();
+        let packet = self.packet();
+        let (answers_start, answers_end) = (answers[0].1, answers[answers.len() - 1].2);
+        let mut new_packet = Vec::with_capacity(packet.len());
+        new_packet.extend_from_slice(&packet[..answers_start]);
+        let mut ancount = 0;
+        for (i, &(_, offset, offset_next)) in answers.iter().enumerate() {
+            if removed[i] {
+                continue;
+            }
+            ancount += 1;
+            match final_rrset.iter().position(|&j| j == i) {
+                None => new_packet.extend_from_slice(&packet[offset..offset_next]),
+                Some(j) => {
+                    let name_end = offset + Compress::raw_name_len(&packet[offset..]);
+                    new_packet.extend(renamed_names[j].as_ref().unwrap_or(&qname));
+                    let rr_start = new_packet.len();
+                    new_packet.extend_from_slice(&packet[name_end..offset_next]);
+                    BigEndian::write_u32(&mut new_packet[rr_start + DNS_RR_TTL_OFFSET..], ttl);
+                }
+            }
+        }
+        new_packet.extend_from_slice(&packet[answers_end..]);
+        let shift = |offset: Option<usize>| offset.map(|x| x + new_packet.len() - packet.len());
+        let (offset_nameservers, offset_additional, offset_edns) = (
+            shift(self.offset_nameservers),
+            shift(self.offset_additional),
+            shift(self.offset_edns),
+        );
+        DNSSector::set_ancount(&mut new_packet, ancount);
+        self.packet = Some(new_packet);
+        self.offset_nameservers = offset_nameservers;
+        self.offset_additional = offset_additional;
+        self.offset_edns = offset_edns;
+        Ok(true)
+    }
+
+    /// Returns `true` if two records, given as `(section, start, end)`, are
+    /// in the same section and have the same name, type and class.
+    fn is_same_rrset(&self, a: (Section, usize, usize), b: (Section, usize, usize)) -> bool {
+        if a.0 != b.0 {
+            return false;
+        }
+        let packet = self.packet();
+        let (mut name_a, mut name_b) = (vec![], vec![]);
+        let a_name_end = Compress::copy_uncompressed_name(&mut name_a, packet, a.1).final_offset;
+        let b_name_end = Compress::copy_uncompressed_name(&mut name_b, packet, b.1).final_offset;
+        name_a.eq_ignore_ascii_case(&name_b)
+            && packet[a_name_end..a_name_end + DNS_RR_TTL_OFFSET]
+                == packet[b_name_end..b_name_end + DNS_RR_TTL_OFFSET]
+    }
+
+    /// Checks that this packet is a response to `query`: the `QR` bit must be
+    /// set, and the transaction ID, the opcode and the question must be the
+    /// same as in the query. Names are compared according to `policy`.
+    ///
+    /// Only the question section is compared, since queries are not
+    /// expected to include more than one question.
+    pub fn validate_response_for(
+        &mut self,
+        query: &ParsedPacket,
+        policy: MatchPolicy,
+    ) -> Result<(), ResponseMismatch> {
+        if !self.is_response() {
+            return Err(ResponseMismatch::NotAResponse);
+        }
+        if self.tid() != query.tid() {
+            return Err(ResponseMismatch::Tid);
+        }
+        if self.opcode() != query.opcode() {
+            return Err(ResponseMismatch::Opcode);
+        }
+        if DNSSector::qdcount(self.packet()) != DNSSector::qdcount(query.packet()) {
+            return Err(ResponseMismatch::QuestionCount);
+        }
+        let (query_name, query_type, query_class) = match query.uncached_question_raw0() {
+            None => return Ok(()),
+            Some(question) => question,
+        };
+        let (name, rr_type, rr_class) = self
+            .question_raw0()
+            .ok_or(ResponseMismatch::QuestionCount)?;
+        let same_name = if policy.exact_case {
+            name == query_name
+        } else {
+            name.eq_ignore_ascii_case(&query_name)
+        };
+        if !same_name {
+            return Err(ResponseMismatch::QuestionName);
+        }
+        if rr_type != query_type {
+            return Err(ResponseMismatch::QuestionType);
+        }
+        if rr_class != query_class {
+            return Err(ResponseMismatch::QuestionClass);
+        }
+        Ok(())
+    }
+
+    /// Randomizes the case of the letters of the question name in place (0x20
+    /// encoding), and returns the original name, in raw format including the
+    /// trailing `0`. This name can later be given to `echoes_question_case()`
+    /// and `restore_question_case()`.
+    ///
+    /// Labels reached through a compression pointer are left untouched.
+    pub fn randomize_question_case(&mut self) -> Result<Vec<u8>, Error> {
+        let original_name = self
+            .question_raw0()
+            .map(|(name, _, _)| name.to_vec())
+            .ok_or(DSError::PropertyNotFound)?;
+        let mut rng = rand::rng();
+        {
+            let mut it = self.into_iter_question().ok_or(DSError::PropertyNotFound)?;
+            let raw = it.raw_mut();
+            let mut offset = raw.offset;
+            while offset < raw.name_end {
+                let label_len = match raw.packet[offset] {
+                    len if len & 0xc0 == 0xc0 => break,
+                    len => len as usize,
+                };
+                for c in &mut raw.packet[offset + 1..offset + 1 + label_len] {
+                    if c.is_ascii_alphabetic() && rng.random::<bool>() {
+                        *c ^= 0x20;
+                    }
+                }
+                offset += 1 + label_len;
+            }
+        }
+        self.cached = None;
+        Ok(original_name)
+    }
+
+    /// Returns `true` if the question name has the exact same case as
+    /// `raw_name`, which includes the trailing `0`. This is the check to
+    /// perform on responses to queries sent with a randomized case.
+    pub fn echoes_question_case(&mut self, raw_name: &[u8]) -> bool {
+        matches!(self.question_raw0(), Some((name, _, _)) if name == raw_name)
+    }
+
+    /// Restores the case of `original_name` (in raw format, including the
+    /// trailing `0`) in all the names that match it regardless of case. This
+    /// undoes the case randomization in a response, before forwarding it to
+    /// the client that sent the original query.
+    pub fn restore_question_case(&mut self, original_name: &[u8]) -> Result<(), Error> {
+        if original_name.first().is_none_or(|&len| len == 0) {
+            return Ok(());
+        }
+        self.rename_with_raw_names(original_name, original_name, false)
+    }
+
     /// Replaces `source_name` with `target_name` in all names, in all records.
     /// If `match_suffix` is `true`, do suffix matching instead of exact
     /// matching This allows renaming `*.example.com` into `*.example.net`.
@@ -467,10 +1758,10 @@ impl ParsedPacket {
         source_name: &[u8],
         match_suffix: bool,
     ) -> Result<(), Error> {
+        self.validate()?;
         let packet = Renamer::rename_with_raw_names(self, target_name, source_name, match_suffix)?;
-        self.packet = Some(packet);
-        let dns_sector = DNSSector::new(self.packet.take().unwrap())?;
-        let parsed_packet = dns_sector.parse()?; // XXX - This can be recomputed on the fly by Renamer::rename_with_raw_names()
+        let dns_sector = DNSSector::new(packet)?;
+        let parsed_packet = dns_sector.parse_with(self.parse_options)?; // XXX - This can be recomputed on the fly by Renamer::rename_with_raw_names()
         self.offset_question = parsed_packet.offset_question;
         self.offset_answers = parsed_packet.offset_answers;
         self.offset_nameservers = parsed_packet.offset_nameservers;
@@ -480,7 +1771,10 @@ impl ParsedPacket {
         assert_eq!(self.ext_rcode, parsed_packet.ext_rcode);
         assert_eq!(self.edns_version, parsed_packet.edns_version);
         assert_eq!(self.ext_flags, parsed_packet.ext_flags);
+        self.packet = Some(parsed_packet.into_packet());
         self.maybe_compressed = true;
+        self.pending_validation = false;
+        self.cached = None;
         Ok(())
     }
 }
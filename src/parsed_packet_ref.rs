@@ -0,0 +1,253 @@
+use std::borrow::Cow;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::compress::*;
+use crate::constants::*;
+use crate::parse_options::*;
+use crate::parsed_packet::*;
+use crate::record_view::*;
+
+/// A `ParsedPacketRef` structure is a read-only version of `ParsedPacket`,
+/// that borrows the packet it was built from instead of owning it.
+///
+/// It is returned by `DNSSector::parse()` when the `DNSSector` was created
+/// from a `&[u8]` slice, after the same validation as for owned packets.
+/// The packet is only copied if records had to be dropped from a truncated
+/// response, or when converting it into a `ParsedPacket` in order to modify
+/// it.
+#[derive(Clone, Debug)]
+pub struct ParsedPacketRef<'a> {
+    pub packet: Cow<'a, [u8]>,
+    pub offset_question: Option<usize>,
+    pub offset_answers: Option<usize>,
+    pub offset_nameservers: Option<usize>,
+    pub offset_additional: Option<usize>,
+    pub offset_edns: Option<usize>,
+    pub edns_count: u16,
+    pub ext_rcode: Option<u8>,
+    pub edns_version: Option<u8>,
+    pub ext_flags: Option<u16>,
+    pub max_payload: usize,
+    pub parse_options: ParseOptions,
+    pub dropped_records: Option<DroppedRecords>,
+}
+
+impl ParsedPacketRef<'_> {
+    /// Returns a reference to the packet
+    #[inline]
+    pub fn packet(&self) -> &[u8] {
+        &self.packet
+    }
+
+    /// Converts a `ParsedPacketRef` into a raw packet, copying it if it is
+    /// borrowed.
+    #[inline]
+    pub fn into_packet(self) -> Vec<u8> {
+        self.packet.into_owned()
+    }
+
+    /// Converts a `ParsedPacketRef` into a mutable `ParsedPacket`, copying the
+    /// packet if it is borrowed. The packet is not parsed again.
+    pub fn into_parsed_packet(self) -> ParsedPacket {
+        ParsedPacket {
+            packet: Some(self.packet.into_owned()),
+            offset_question: self.offset_question,
+            offset_answers: self.offset_answers,
+            offset_nameservers: self.offset_nameservers,
+            offset_additional: self.offset_additional,
+            offset_edns: self.offset_edns,
+            edns_count: self.edns_count,
+            ext_rcode: self.ext_rcode,
+            edns_version: self.edns_version,
+            ext_flags: self.ext_flags,
+            maybe_compressed: true,
+            max_payload: self.max_payload,
+            cached: None,
+            parse_options: self.parse_options,
+            dropped_records: self.dropped_records,
+            pending_validation: false,
+        }
+    }
+
+    /// Iterates over the question section.
+    pub fn iter_question(&self) -> RecordViewIterator<'_> {
+        RecordViewIterator::new(
+            self.packet(),
+            Section::Question,
+            self.offset_question,
+            false,
+        )
+    }
+
+    /// Iterates over the answer section.
+    pub fn iter_answer(&self) -> RecordViewIterator<'_> {
+        RecordViewIterator::new(self.packet(), Section::Answer, self.offset_answers, false)
+    }
+
+    /// Iterates over the list of name servers.
+    pub fn iter_nameservers(&self) -> RecordViewIterator<'_> {
+        RecordViewIterator::new(
+            self.packet(),
+            Section::NameServers,
+            self.offset_nameservers,
+            false,
+        )
+    }
+
+    /// Iterates over the additional section - OPT RRs are skipped.
+    pub fn iter_additional(&self) -> RecordViewIterator<'_> {
+        RecordViewIterator::new(
+            self.packet(),
+            Section::Additional,
+            self.offset_additional,
+            true,
+        )
+    }
+
+    /// Iterates over the additional section - OPT RRs are included.
+    pub fn iter_additional_including_opt(&self) -> RecordViewIterator<'_> {
+        RecordViewIterator::new(
+            self.packet(),
+            Section::Additional,
+            self.offset_additional,
+            false,
+        )
+    }
+
+    /// Returns the transaction ID.
+    #[inline]
+    pub fn tid(&self) -> u16 {
+        BigEndian::read_u16(&self.packet()[DNS_TID_OFFSET..])
+    }
+
+    /// Returns the flags, including extended flags.
+    /// The extended flags optionally obtained using edns are exposed as the
+    /// highest 16 bits, instead of having distinct sets of flags.
+    /// The opcode and rcode are masked, like `ParsedPacket::flags()` does.
+    pub fn flags(&self) -> u32 {
+        let mut rflags = BigEndian::read_u16(&self.packet()[DNS_FLAGS_OFFSET..]);
+        rflags &= !0x7800; // mask opcode
+        rflags &= !0x000f; // mask rcode
+        ((self.ext_flags.unwrap_or(0) as u32) << 16) | (rflags as u32)
+    }
+
+    /// Check if this is a question with the DO bit, or a response with the AD
+    /// bit
+    pub fn dnssec(&self) -> bool {
+        let flags = self.flags();
+        if flags & DNS_FLAG_QR == 0 {
+            (flags & DNS_FLAG_DO) != 0
+        } else {
+            (flags & DNS_FLAG_AD) != 0
+        }
+    }
+
+    /// Check if this is a response
+    #[inline]
+    pub fn is_response(&self) -> bool {
+        self.flags() & DNS_FLAG_QR == DNS_FLAG_QR
+    }
+
+    /// Returns the return code.
+    #[inline]
+    pub fn rcode(&self) -> u8 {
+        let rflags = self.packet()[DNS_FLAGS_OFFSET + 1];
+        rflags & 0x0f
+    }
+
+    /// Returns the opcode.
+    #[inline]
+    pub fn opcode(&self) -> u8 {
+        let rflags = self.packet()[DNS_FLAGS_OFFSET];
+        (rflags & 0x78) >> 3
+    }
+
+    /// Returns the opcode, as an `Opcode`.
+    #[inline]
+    pub fn typed_opcode(&self) -> Opcode {
+        Opcode::from(self.opcode())
+    }
+
+    /// Returns the 12-bit extended return code.
+    #[inline]
+    pub fn extended_rcode(&self) -> u16 {
+        ((self.ext_rcode.unwrap_or(0) as u16) << 4) | (self.rcode() as u16)
+    }
+
+    /// Returns the extended return code, as an `Rcode`.
+    #[inline]
+    pub fn typed_rcode(&self) -> Rcode {
+        Rcode::from(self.extended_rcode())
+    }
+
+    /// Returns `true` if `flag` is set.
+    #[inline]
+    pub fn flag(&self, flag: Flag) -> bool {
+        self.flags() & flag.mask() != 0
+    }
+
+    /// Returns `true` if records had to be dropped from a truncated response.
+    #[inline]
+    pub fn is_partial(&self) -> bool {
+        self.dropped_records.is_some()
+    }
+
+    /// Maximum payload size when using UDP
+    #[inline]
+    pub fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+
+    /// Returns the question as a raw vector, without case conversion, as well
+    /// as the query type and class Names include a trailing `0`
+    pub fn question_raw0(&self) -> Option<(Vec<u8>, u16, u16)> {
+        let offset = self.offset_question?;
+        let mut name = Vec::with_capacity(DNS_MAX_HOSTNAME_LEN);
+        let offset =
+            Compress::copy_uncompressed_name(&mut name, self.packet(), offset).final_offset;
+        let (rr_type, rr_class) = self.qtype_qclass_at(offset);
+        Some((name, rr_type, rr_class))
+    }
+
+    /// Returns the question as a raw vector, without case conversion, as well
+    /// as the query type and class Names do not include trailing `0`
+    pub fn question_raw(&self) -> Option<(Vec<u8>, u16, u16)> {
+        self.question_raw0().map(|(mut name, rr_type, rr_class)| {
+            name.pop();
+            (name, rr_type, rr_class)
+        })
+    }
+
+    /// Returns the question as a string, converted to lower-case, as well as
+    /// the query type and class
+    pub fn question(&self) -> Option<(Vec<u8>, u16, u16)> {
+        let offset = self.offset_question?;
+        let mut name_str = Compress::raw_name_to_str(self.packet(), offset);
+        name_str.make_ascii_lowercase();
+        let offset = offset + Compress::raw_name_len(&self.packet()[offset..]);
+        let (rr_type, rr_class) = self.qtype_qclass_at(offset);
+        Some((name_str, rr_type, rr_class))
+    }
+
+    /// Return the query type and class
+    pub fn qtype_qclass(&self) -> Option<(u16, u16)> {
+        let offset = self.offset_question?;
+        let offset = offset + Compress::raw_name_len(&self.packet()[offset..]);
+        Some(self.qtype_qclass_at(offset))
+    }
+
+    /// Returns the type and class of the question whose name ends at `offset`.
+    fn qtype_qclass_at(&self, offset: usize) -> (u16, u16) {
+        let rdata = &self.packet()[offset..];
+        let rr_type = BigEndian::read_u16(&rdata[DNS_RR_TYPE_OFFSET..]);
+        let rr_class = BigEndian::read_u16(&rdata[DNS_RR_CLASS_OFFSET..]);
+        (rr_type, rr_class)
+    }
+}
+
+impl From<ParsedPacketRef<'_>> for ParsedPacket {
+    fn from(parsed_packet_ref: ParsedPacketRef<'_>) -> Self {
+        parsed_packet_ref.into_parsed_packet()
+    }
+}
@@ -80,7 +80,6 @@ impl DNSIterable for QuestionIterator<'_> {
                 if count == 0 {
                     return None;
                 }
-                debug_assert_eq!(count, 1);
                 rr_iterator.rrs_left = count;
                 rr_iterator.offset_next = rr_iterator.parsed_packet.offset_question.unwrap();
             }
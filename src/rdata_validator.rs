@@ -0,0 +1,263 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::constants::*;
+use crate::dns_sector::*;
+use crate::errors::*;
+
+/// Size of the fixed part of `RRSIG` and `SIG` records, before the signer
+/// name.
+const RRSIG_FIXED_SIZE: usize = 18;
+
+/// Size of the fixed part of `NAPTR` records, before the character strings.
+const NAPTR_FIXED_SIZE: usize = 4;
+
+/// Size of the fixed part of `SRV` records, before the target name.
+const SRV_FIXED_SIZE: usize = 6;
+
+/// Maximum size of a type bitmap window.
+const TYPE_BITMAP_MAX_WINDOW_LEN: usize = 32;
+
+/// `SvcParamKey` values with a well-known format (RFC 9460).
+const SVCB_KEY_MANDATORY: u16 = 0;
+const SVCB_KEY_ALPN: u16 = 1;
+const SVCB_KEY_NO_DEFAULT_ALPN: u16 = 2;
+const SVCB_KEY_PORT: u16 = 3;
+const SVCB_KEY_IPV4HINT: u16 = 4;
+const SVCB_KEY_IPV6HINT: u16 = 6;
+
+/// Structural validation of the data of untrusted records.
+///
+/// Types that embed names or variable-length fields are checked so that
+/// accessing their content later never goes past the record boundaries.
+/// Names embedded in these records must not be compressed. Types that are
+/// not listed here are treated as opaque data.
+pub struct RdataValidator;
+
+impl RdataValidator {
+    /// Checks the data of a record of type `rr_type`, stored in `packet` at
+    /// `offset`, for `rdlen` bytes. The caller must have checked that the
+    /// packet contains at least `offset + rdlen` bytes.
+    pub fn check(rr_type: u16, packet: &[u8], offset: usize, rdlen: usize) -> Result<(), Error> {
+        let packet = &packet[..offset + rdlen];
+        match rr_type {
+            x if x == Type::TXT.into() || x == Type::SPF.into() => {
+                Self::check_character_strings(packet, offset)
+            }
+            x if x == Type::HINFO.into() => {
+                let offset = Self::check_character_string(packet, offset)?;
+                let offset = Self::check_character_string(packet, offset)?;
+                Self::ensure_end(packet, offset, "Unexpected data after HINFO rdata")
+            }
+            x if x == Type::SRV.into() => {
+                Self::ensure_len(rdlen, SRV_FIXED_SIZE + 1)?;
+                let offset = Self::check_name(packet, offset + SRV_FIXED_SIZE)?;
+                Self::ensure_end(packet, offset, "Unexpected data after name in SRV rdata")
+            }
+            x if x == Type::NAPTR.into() => {
+                Self::ensure_len(rdlen, NAPTR_FIXED_SIZE + 3 + 1)?;
+                let mut offset = offset + NAPTR_FIXED_SIZE;
+                for _ in 0..3 {
+                    offset = Self::check_character_string(packet, offset)?;
+                }
+                let offset = Self::check_name(packet, offset)?;
+                Self::ensure_end(packet, offset, "Unexpected data after name in NAPTR rdata")
+            }
+            x if x == Type::RRSIG.into() || x == Type::SIG.into() => {
+                Self::ensure_len(rdlen, RRSIG_FIXED_SIZE + 1)?;
+                Self::check_name(packet, offset + RRSIG_FIXED_SIZE)?;
+                Ok(())
+            }
+            x if x == Type::NSEC.into() => {
+                Self::ensure_len(rdlen, 1)?;
+                let offset = Self::check_name(packet, offset)?;
+                Self::check_type_bitmaps(packet, offset)
+            }
+            x if x == Type::NSEC3.into() => {
+                Self::ensure_len(rdlen, 5)?;
+                let offset = Self::check_salt(packet, offset + 4)?;
+                if offset >= packet.len() {
+                    bail!(DSError::InvalidPacket("Missing hash in NSEC3 rdata"));
+                }
+                let hash_len = packet[offset] as usize;
+                if hash_len == 0 || hash_len > packet.len() - offset - 1 {
+                    bail!(DSError::InvalidPacket("Invalid hash length in NSEC3 rdata"));
+                }
+                Self::check_type_bitmaps(packet, offset + 1 + hash_len)
+            }
+            x if x == Type::NSEC3PARAM.into() => {
+                Self::ensure_len(rdlen, 5)?;
+                let offset = Self::check_salt(packet, offset + 4)?;
+                Self::ensure_end(packet, offset, "Unexpected data after NSEC3PARAM salt")
+            }
+            x if x == Type::SVCB.into() || x == Type::HTTPS.into() => {
+                Self::ensure_len(rdlen, 2 + 1)?;
+                let offset = Self::check_name(packet, offset + 2)?;
+                Self::check_svc_params(packet, offset)
+            }
+            x if x == Type::CAA.into() => {
+                Self::ensure_len(rdlen, 2)?;
+                let tag_len = packet[offset + 1] as usize;
+                if tag_len == 0 || tag_len > rdlen - 2 {
+                    bail!(DSError::InvalidPacket("Invalid tag length in CAA rdata"));
+                }
+                let tag = &packet[offset + 2..offset + 2 + tag_len];
+                if !tag.iter().all(u8::is_ascii_alphanumeric) {
+                    bail!(DSError::InvalidPacket("Invalid tag in CAA rdata"));
+                }
+                Ok(())
+            }
+            x if x == Type::TLSA.into() || x == Type::SMIMEA.into() => Self::ensure_len(rdlen, 3),
+            x if x == Type::SSHFP.into() => Self::ensure_len(rdlen, 3),
+            x if x == Type::DS.into()
+                || x == Type::CDS.into()
+                || x == Type::DNSKEY.into()
+                || x == Type::CDNSKEY.into() =>
+            {
+                Self::ensure_len(rdlen, 5)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a name that must not be compressed, and returns the location
+    /// right after it.
+    #[inline]
+    fn check_name(packet: &[u8], offset: usize) -> Result<usize, Error> {
+        if offset >= packet.len() {
+            bail!(DSError::InvalidPacket("Missing name in record data"));
+        }
+        DNSSector::check_uncompressed_name(packet, offset)
+    }
+
+    /// Makes sure that the record data is at least `min_len` bytes long.
+    #[inline]
+    fn ensure_len(rdlen: usize, min_len: usize) -> Result<(), Error> {
+        if rdlen < min_len {
+            bail!(DSError::InvalidPacket("Record data is too short"));
+        }
+        Ok(())
+    }
+
+    /// Makes sure that `offset` is the end of the record data.
+    #[inline]
+    fn ensure_end(packet: &[u8], offset: usize, reason: &'static str) -> Result<(), Error> {
+        if offset != packet.len() {
+            bail!(DSError::InvalidPacket(reason));
+        }
+        Ok(())
+    }
+
+    /// Checks a character string (a length followed by up to 255 bytes), and
+    /// returns the location right after it.
+    fn check_character_string(packet: &[u8], offset: usize) -> Result<usize, Error> {
+        if offset >= packet.len() {
+            bail!(DSError::InvalidPacket("Missing character string"));
+        }
+        let len = packet[offset] as usize;
+        if len > packet.len() - offset - 1 {
+            bail!(DSError::InvalidPacket(
+                "Character string exceeds record data"
+            ));
+        }
+        Ok(offset + 1 + len)
+    }
+
+    /// Checks that the record data, starting at `offset`, is entirely made
+    /// of character strings.
+    fn check_character_strings(packet: &[u8], mut offset: usize) -> Result<(), Error> {
+        while offset < packet.len() {
+            offset = Self::check_character_string(packet, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the salt of `NSEC3` and `NSEC3PARAM` records, and returns the
+    /// location right after it.
+    fn check_salt(packet: &[u8], offset: usize) -> Result<usize, Error> {
+        let salt_len = packet[offset] as usize;
+        if salt_len > packet.len() - offset - 1 {
+            bail!(DSError::InvalidPacket("Salt exceeds record data"));
+        }
+        Ok(offset + 1 + salt_len)
+    }
+
+    /// Checks the type bitmaps of `NSEC` and `NSEC3` records (RFC 4034
+    /// section 4.1.2): windows must be in increasing order, and each bitmap
+    /// must be between 1 and 32 bytes long.
+    fn check_type_bitmaps(packet: &[u8], mut offset: usize) -> Result<(), Error> {
+        let mut previous_window = None;
+        while offset < packet.len() {
+            if packet.len() - offset < 2 {
+                bail!(DSError::InvalidPacket("Truncated type bitmap window"));
+            }
+            let window = packet[offset];
+            if matches!(previous_window, Some(previous_window) if window <= previous_window) {
+                bail!(DSError::InvalidPacket(
+                    "Type bitmap windows are not in increasing order"
+                ));
+            }
+            let len = packet[offset + 1] as usize;
+            if len == 0 || len > TYPE_BITMAP_MAX_WINDOW_LEN {
+                bail!(DSError::InvalidPacket("Invalid type bitmap length"));
+            }
+            if len > packet.len() - offset - 2 {
+                bail!(DSError::InvalidPacket("Type bitmap exceeds record data"));
+            }
+            previous_window = Some(window);
+            offset += 2 + len;
+        }
+        Ok(())
+    }
+
+    /// Checks the parameters of `SVCB` and `HTTPS` records (RFC 9460 section
+    /// 2.2): keys must be in strictly increasing order, values must fit in the
+    /// record data, and values of well-known keys must have a valid length.
+    fn check_svc_params(packet: &[u8], mut offset: usize) -> Result<(), Error> {
+        let mut previous_key = None;
+        while offset < packet.len() {
+            if packet.len() - offset < 4 {
+                bail!(DSError::InvalidPacket("Truncated SvcParam"));
+            }
+            let key = BigEndian::read_u16(&packet[offset..]);
+            if matches!(previous_key, Some(previous_key) if key <= previous_key) {
+                bail!(DSError::InvalidPacket(
+                    "SvcParam keys are not in strictly increasing order"
+                ));
+            }
+            let len = BigEndian::read_u16(&packet[offset + 2..]) as usize;
+            offset += 4;
+            if len > packet.len() - offset {
+                bail!(DSError::InvalidPacket("SvcParam value exceeds record data"));
+            }
+            let value = &packet[..offset + len];
+            let valid = match key {
+                SVCB_KEY_MANDATORY => len > 0 && len.is_multiple_of(2),
+                SVCB_KEY_ALPN => len > 0 && Self::is_valid_alpn(value, offset),
+                SVCB_KEY_NO_DEFAULT_ALPN => len == 0,
+                SVCB_KEY_PORT => len == 2,
+                SVCB_KEY_IPV4HINT => len > 0 && len.is_multiple_of(4),
+                SVCB_KEY_IPV6HINT => len > 0 && len.is_multiple_of(16),
+                _ => true,
+            };
+            if !valid {
+                bail!(DSError::InvalidPacket("Invalid SvcParam value"));
+            }
+            previous_key = Some(key);
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the data starting at `offset` is a list of
+    /// non-empty character strings.
+    fn is_valid_alpn(packet: &[u8], mut offset: usize) -> bool {
+        while offset < packet.len() {
+            let len = packet[offset] as usize;
+            if len == 0 || len > packet.len() - offset - 1 {
+                return false;
+            }
+            offset += 1 + len;
+        }
+        true
+    }
+}
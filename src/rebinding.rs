@@ -0,0 +1,183 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::constants::*;
+use crate::errors::*;
+use crate::ip_prefix::*;
+use crate::parsed_packet::*;
+use crate::response_options::*;
+use crate::rr_iterator::*;
+
+/// What a `RebindingGuard` does with a response including private or
+/// reserved addresses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RebindingAction {
+    /// The offending records are removed.
+    Drop,
+    /// The response is replaced with a `REFUSED` response.
+    Refuse,
+    /// The response is replaced with a `NXDOMAIN` response.
+    NxDomain,
+}
+
+/// A `RebindingGuard` protects clients such as web browsers against DNS
+/// rebinding attacks, by rejecting `A` and `AAAA` records with private,
+/// loopback, link-local, shared (CGNAT), documentation, unique local and
+/// other special-use addresses. These are the same addresses `Dns64`
+/// excludes by default.
+///
+/// Names from the allow-list, and their subdomains, may still resolve to
+/// these addresses.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RebindingGuard {
+    /// The prefixes that are rejected.
+    pub blocked_prefixes: Vec<IpPrefix>,
+    /// Lower-case, dot-delimited domains that may resolve to blocked
+    /// addresses.
+    pub allowed_domains: Vec<Vec<u8>>,
+    pub action: RebindingAction,
+}
+
+impl Default for RebindingGuard {
+    fn default() -> Self {
+        RebindingGuard::new()
+    }
+}
+
+impl RebindingGuard {
+    /// Creates a guard rejecting all special-use addresses, that removes the
+    /// offending records.
+    pub fn new() -> Self {
+        let blocked_prefixes = SPECIAL_USE_IPV4_PREFIXES
+            .iter()
+            .chain(SPECIAL_USE_IPV6_PREFIXES.iter())
+            .map(|prefix| prefix.parse().unwrap())
+            .collect();
+        RebindingGuard {
+            blocked_prefixes,
+            allowed_domains: vec![],
+            action: RebindingAction::Drop,
+        }
+    }
+
+    /// Also rejects the addresses from `prefix`.
+    pub fn with_blocked_prefix(mut self, prefix: IpPrefix) -> Self {
+        self.blocked_prefixes.push(prefix);
+        self
+    }
+
+    /// Allows `domain` and its subdomains to resolve to blocked addresses.
+    pub fn with_allowed_domain(mut self, domain: &[u8]) -> Self {
+        let mut domain = domain.to_ascii_lowercase();
+        if domain.last() == Some(&b'.') {
+            domain.pop();
+        }
+        self.allowed_domains.push(domain);
+        self
+    }
+
+    /// Sets what to do with responses including blocked addresses.
+    pub fn with_action(mut self, action: RebindingAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Returns `true` if `ip` is a blocked address. IPv4 addresses embedded
+    /// into NAT64 (`64:ff9b::/96`) and 6to4 (`2002::/16`) addresses are
+    /// checked as well.
+    pub fn is_blocked(&self, ip: &IpAddr) -> bool {
+        let embedded_ipv4 = Self::embedded_ipv4(ip).map(IpAddr::V4);
+        self.blocked_prefixes.iter().any(|prefix| {
+            prefix.contains(ip) || embedded_ipv4.is_some_and(|ipv4| prefix.contains(&ipv4))
+        })
+    }
+
+    /// Returns `true` if `name`, lower-case and dot-delimited, is an allowed
+    /// domain or a subdomain of one.
+    pub fn is_allowed_domain(&self, name: &[u8]) -> bool {
+        self.allowed_domains.iter().any(|domain| {
+            name == &domain[..]
+                || domain.is_empty()
+                || (name.len() > domain.len()
+                    && name.ends_with(domain)
+                    && name[name.len() - domain.len() - 1] == b'.')
+        })
+    }
+
+    /// Checks the `A` and `AAAA` records from the answer and additional
+    /// sections of `response`, and applies the action to the ones with a
+    /// blocked address, unless the question name or the record name is
+    /// allowed.
+    ///
+    /// `REFUSED` and `NXDOMAIN` responses include a `Blocked` Extended DNS
+    /// Error if the response has an `OPT` record.
+    ///
+    /// Returns the number of offending records, `0` meaning that the
+    /// response was left untouched.
+    pub fn apply(&self, response: &mut ParsedPacket) -> Result<usize, Error> {
+        if response
+            .question()
+            .is_some_and(|(name, ..)| self.is_allowed_domain(&name))
+        {
+            return Ok(0);
+        }
+        let mut offending = 0;
+        let mut it = response.try_into_iter_answer()?;
+        while let Some(item) = it {
+            if self.is_offending(&item.name(), item.rr_ip()) {
+                offending += 1;
+            }
+            it = item.next();
+        }
+        let mut it = response.try_into_iter_additional()?;
+        while let Some(item) = it {
+            if self.is_offending(&item.name(), item.rr_ip()) {
+                offending += 1;
+            }
+            it = item.next();
+        }
+        if offending == 0 {
+            return Ok(0);
+        }
+        let rcode = match self.action {
+            RebindingAction::Drop => {
+                response.retain(|record_view| {
+                    record_view.section == Section::NameServers
+                        || !self.is_offending(&record_view.name(), record_view.rr_ip())
+                })?;
+                return Ok(offending);
+            }
+            RebindingAction::Refuse => Rcode::REFUSED,
+            RebindingAction::NxDomain => Rcode::NXDOMAIN,
+        };
+        let options = ResponseOptions::default()
+            .with_rcode(rcode)
+            .with_recursion_available(response.ra())
+            .with_extended_error(ExtendedError::BLOCKED);
+        *response = response.to_response(options)?;
+        Ok(offending)
+    }
+
+    /// Returns the IPv4 address embedded into a NAT64 address using the
+    /// Well-Known Prefix (RFC 6052), or into a 6to4 address (RFC 3056).
+    fn embedded_ipv4(ip: &IpAddr) -> Option<Ipv4Addr> {
+        let octets = match ip {
+            IpAddr::V4(_) => return None,
+            IpAddr::V6(ip) => ip.octets(),
+        };
+        let ipv4 = match octets {
+            [0, 0x64, 0xff, 0x9b, 0, 0, 0, 0, 0, 0, 0, 0, a, b, c, d] => [a, b, c, d],
+            [0x20, 0x02, a, b, c, d, ..] => [a, b, c, d],
+            _ => return None,
+        };
+        Some(Ipv4Addr::from(ipv4))
+    }
+
+    /// Returns `true` if a record named `name` with the address `ip` must be
+    /// rejected. Records without an address never are.
+    fn is_offending(&self, name: &[u8], ip: Result<IpAddr, Error>) -> bool {
+        match ip {
+            Ok(ip) => self.is_blocked(&ip) && !self.is_allowed_domain(name),
+            Err(_) => false,
+        }
+    }
+}
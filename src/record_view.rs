@@ -0,0 +1,210 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::compress::*;
+use crate::constants::*;
+use crate::dns_sector::*;
+use crate::errors::*;
+use crate::rr_iterator::*;
+
+/// A read-only view of a record from a validated packet.
+/// Views are only created by this crate, from the records of a validated
+/// packet, so that their offsets are always valid.
+///
+/// Questions don't have a TTL nor any data: the `rr_ttl()`, `rr_rdlen()`,
+/// `rdata()`, `rr_rd()` and `rr_ip()` accessors return `None` or an error for
+/// records from the question section.
+#[derive(Copy, Clone, Debug)]
+pub struct RecordView<'t> {
+    pub(crate) packet: &'t [u8],
+    pub(crate) section: Section,
+    /// Offset to the record.
+    pub(crate) offset: usize,
+    /// Offset to the data right after the name.
+    pub(crate) name_end: usize,
+}
+
+impl<'t> RecordView<'t> {
+    /// Returns the section the record belongs to.
+    #[inline]
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    /// Accesses the raw packet data.
+    #[inline]
+    pub fn raw(&self) -> RRRaw<'t> {
+        RRRaw {
+            packet: self.packet,
+            offset: self.offset,
+            name_end: self.name_end,
+        }
+    }
+
+    /// Returns the offset right after the record.
+    #[inline]
+    pub fn offset_next(&self) -> usize {
+        match self.section {
+            Section::Question => self.name_end + DNS_RR_QUESTION_HEADER_SIZE,
+            _ => RRIterator::skip_rdata(self.packet, self.name_end),
+        }
+    }
+
+    /// Accesses the raw packet data, starting from the name.
+    #[inline]
+    pub fn name_slice(&self) -> &'t [u8] {
+        &self.packet[self.offset..self.name_end]
+    }
+
+    /// Returns the record name (labels are dot-delimited), as a byte vector,
+    /// converted to lower-case.
+    pub fn name(&self) -> Vec<u8> {
+        let mut name = Compress::raw_name_to_str(self.packet, self.offset);
+        name.make_ascii_lowercase();
+        name
+    }
+
+    /// Appends the uncompressed record name (raw format, with labels prefixed
+    /// by their length) to the given vector. Returns the length of the
+    /// uncompressed name.
+    pub fn copy_raw_name(&self, name: &mut Vec<u8>) -> usize {
+        Compress::copy_uncompressed_name(name, self.packet, self.offset).name_len
+    }
+
+    /// Returns the type of the record.
+    #[inline]
+    pub fn rr_type(&self) -> u16 {
+        BigEndian::read_u16(&self.packet[self.name_end + DNS_RR_TYPE_OFFSET..])
+    }
+
+    /// Returns the class of the record.
+    #[inline]
+    pub fn rr_class(&self) -> u16 {
+        BigEndian::read_u16(&self.packet[self.name_end + DNS_RR_CLASS_OFFSET..])
+    }
+
+    /// Returns the TTL of the record, or `None` for a question.
+    #[inline]
+    pub fn rr_ttl(&self) -> Option<u32> {
+        if self.section == Section::Question {
+            return None;
+        }
+        Some(BigEndian::read_u32(
+            &self.packet[self.name_end + DNS_RR_TTL_OFFSET..],
+        ))
+    }
+
+    /// Returns the length of the record data, or `None` for a question.
+    #[inline]
+    pub fn rr_rdlen(&self) -> Option<usize> {
+        if self.section == Section::Question {
+            return None;
+        }
+        Some(BigEndian::read_u16(&self.packet[self.name_end + DNS_RR_RDLEN_OFFSET..]) as usize)
+    }
+
+    /// Returns the record data, or `None` for a question.
+    #[inline]
+    pub fn rdata(&self) -> Option<&'t [u8]> {
+        let offset_rdata = self.name_end + DNS_RR_HEADER_SIZE;
+        Some(&self.packet[offset_rdata..offset_rdata + self.rr_rdlen()?])
+    }
+
+    /// Returns the raw record data.
+    pub fn rr_rd(&self) -> Result<RawRRData<'t>, Error> {
+        if let Ok(ip_addr) = self.rr_ip() {
+            return Ok(RawRRData::IpAddr(ip_addr));
+        }
+        Ok(RawRRData::Data(
+            self.rdata().ok_or(DSError::PropertyNotFound)?,
+        ))
+    }
+
+    /// Retrieves the IP address of an `A` or `AAAA` record.
+    pub fn rr_ip(&self) -> Result<IpAddr, Error> {
+        let rdata = self.rdata().ok_or(DSError::PropertyNotFound)?;
+        match self.rr_type() {
+            x if x == Type::A.into() => {
+                let ip: [u8; 4] = match rdata.try_into() {
+                    Err(_) => bail!(DSError::InvalidPacket("Invalid A record length")),
+                    Ok(ip) => ip,
+                };
+                Ok(IpAddr::V4(Ipv4Addr::from(ip)))
+            }
+            x if x == Type::AAAA.into() => {
+                let ip: [u8; 16] = match rdata.try_into() {
+                    Err(_) => bail!(DSError::InvalidPacket("Invalid AAAA record length")),
+                    Ok(ip) => ip,
+                };
+                Ok(IpAddr::V6(Ipv6Addr::from(ip)))
+            }
+            _ => bail!(DSError::PropertyNotFound),
+        }
+    }
+}
+
+/// An iterator over the records of a section of a validated packet, returning
+/// `RecordView`s.
+#[derive(Clone, Debug)]
+pub struct RecordViewIterator<'t> {
+    packet: &'t [u8],
+    section: Section,
+    offset_next: usize,
+    rrs_left: u16,
+    skip_opt: bool,
+}
+
+impl<'t> RecordViewIterator<'t> {
+    /// Iterates over the records of `section` of a validated packet,
+    /// starting at `offset`. If `offset` is `None`, the section is assumed to
+    /// be empty. `OPT` records are skipped if `skip_opt` is `true`.
+    pub(crate) fn new(
+        packet: &'t [u8],
+        section: Section,
+        offset: Option<usize>,
+        skip_opt: bool,
+    ) -> Self {
+        let rrs_left = match (offset, section) {
+            (None, _) | (Some(_), Section::Edns) => 0,
+            (Some(_), Section::Question) => DNSSector::qdcount(packet),
+            (Some(_), Section::Answer) => DNSSector::ancount(packet),
+            (Some(_), Section::NameServers) => DNSSector::nscount(packet),
+            (Some(_), Section::Additional) => DNSSector::arcount(packet),
+        };
+        RecordViewIterator {
+            packet,
+            section,
+            offset_next: offset.unwrap_or(0),
+            rrs_left,
+            skip_opt,
+        }
+    }
+}
+
+impl<'t> Iterator for RecordViewIterator<'t> {
+    type Item = RecordView<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.rrs_left > 0 {
+            self.rrs_left -= 1;
+            let offset = self.offset_next;
+            let record_view = RecordView {
+                packet: self.packet,
+                section: self.section,
+                offset,
+                name_end: RRIterator::skip_name(self.packet, offset),
+            };
+            self.offset_next = record_view.offset_next();
+            if self.skip_opt && record_view.rr_type() == Type::OPT.into() {
+                continue;
+            }
+            return Some(record_view);
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.rrs_left as usize))
+    }
+}
@@ -331,6 +331,7 @@ impl Renamer {
         if target_name.len() <= 0 || source_name.len() <= 0 {
             bail!(DSError::InvalidName("Empty name"));
         }
+        parsed_packet.validate()?;
         if target_name.len() > DNS_MAX_HOSTNAME_LEN || source_name.len() > DNS_MAX_HOSTNAME_LEN {
             bail!(DSError::InvalidName("Name too long"));
         }
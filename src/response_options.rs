@@ -0,0 +1,62 @@
+use crate::constants::*;
+
+/// A `ResponseOptions` structure describes the response built from a query by
+/// `ParsedPacket::to_response()`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ResponseOptions {
+    /// Return code. Extended return codes require the query to have an `OPT`
+    /// record.
+    pub rcode: Rcode,
+    /// Whether the `AA` (authoritative answer) flag is set.
+    pub authoritative: bool,
+    /// Whether the `RA` (recursion available) flag is set.
+    pub recursion_available: bool,
+    /// UDP payload size advertised in the `OPT` record, if the query had one.
+    pub max_payload: u16,
+    /// Extended DNS Error added to the `OPT` record, if the query had one.
+    pub extended_error: Option<ExtendedError>,
+}
+
+impl Default for ResponseOptions {
+    fn default() -> Self {
+        ResponseOptions {
+            rcode: Rcode::NOERROR,
+            authoritative: false,
+            recursion_available: false,
+            max_payload: DNS_DEFAULT_EDNS_PAYLOAD_SIZE,
+            extended_error: None,
+        }
+    }
+}
+
+impl ResponseOptions {
+    /// Sets the return code.
+    pub fn with_rcode(mut self, rcode: Rcode) -> Self {
+        self.rcode = rcode;
+        self
+    }
+
+    /// Sets whether the response is authoritative.
+    pub fn with_authoritative(mut self, authoritative: bool) -> Self {
+        self.authoritative = authoritative;
+        self
+    }
+
+    /// Sets whether recursion is available.
+    pub fn with_recursion_available(mut self, recursion_available: bool) -> Self {
+        self.recursion_available = recursion_available;
+        self
+    }
+
+    /// Sets the advertised UDP payload size.
+    pub fn with_max_payload(mut self, max_payload: u16) -> Self {
+        self.max_payload = max_payload;
+        self
+    }
+
+    /// Sets the Extended DNS Error code.
+    pub fn with_extended_error(mut self, extended_error: ExtendedError) -> Self {
+        self.extended_error = Some(extended_error);
+        self
+    }
+}
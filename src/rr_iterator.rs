@@ -124,8 +124,13 @@ pub trait DNSIterable {
         }
         let (uncompressed, new_offset_next) = {
             let ref_offset_next = self.offset_next();
+            let parse_options = self.parsed_packet().parse_options;
             let compressed = self.raw_mut().packet;
-            Compress::uncompress_with_previous_offset(compressed, ref_offset_next)?
+            Compress::uncompress_with_previous_offset_and_options(
+                compressed,
+                ref_offset_next,
+                parse_options,
+            )?
         };
         self.parsed_packet_mut().packet = Some(uncompressed);
         self.set_offset_next(new_offset_next);
@@ -203,6 +208,7 @@ pub trait TypedIterable {
                 return Ok(());
             }
             let offset = self.offset().ok_or(DSError::VoidRecord)?;
+            let max_packet_size = self.parsed_packet().parse_options.limits.max_packet_size;
             let packet = &mut self.parsed_packet_mut().packet_mut();
             let packet_len = packet.len();
             if shift > 0 {
@@ -210,6 +216,9 @@ pub trait TypedIterable {
                 if new_packet_len > 0xffff {
                     bail!(DSError::PacketTooLarge);
                 }
+                if new_packet_len > max_packet_size {
+                    bail!(DSError::LimitExceeded("Packet too large"));
+                }
                 packet.resize(new_packet_len, 0);
                 debug_assert_eq!(
                     new_packet_len,
@@ -258,8 +267,13 @@ pub trait TypedIterable {
         if self.parsed_packet().maybe_compressed {
             let (uncompressed, new_offset) = {
                 let ref_offset = self.offset().ok_or(DSError::VoidRecord)?;
+                let parse_options = self.parsed_packet().parse_options;
                 let compressed = self.raw_mut().packet;
-                Compress::uncompress_with_previous_offset(compressed, ref_offset)?
+                Compress::uncompress_with_previous_offset_and_options(
+                    compressed,
+                    ref_offset,
+                    parse_options,
+                )?
             };
             self.parsed_packet_mut().packet = Some(uncompressed);
             self.set_offset(new_offset);
@@ -290,8 +304,13 @@ pub trait TypedIterable {
         if self.parsed_packet().maybe_compressed {
             let (uncompressed, new_offset) = {
                 let ref_offset = self.offset().expect("delete() called on a tombstone");
+                let parse_options = self.parsed_packet().parse_options;
                 let compressed = self.raw_mut().packet;
-                Compress::uncompress_with_previous_offset(compressed, ref_offset)?
+                Compress::uncompress_with_previous_offset_and_options(
+                    compressed,
+                    ref_offset,
+                    parse_options,
+                )?
             };
             self.parsed_packet_mut().packet = Some(uncompressed);
             self.set_offset(new_offset);
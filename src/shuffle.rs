@@ -0,0 +1,26 @@
+use std::net::IpAddr;
+
+/// A `ShuffleStrategy` describes how records are reordered inside their
+/// RRset by `ParsedPacket::shuffle_rrsets()`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShuffleStrategy {
+    /// Records are shuffled randomly.
+    Random,
+    /// Records are rotated by the given counter, which is usually
+    /// incremented for every query, for a round-robin order.
+    Rotate(u64),
+    /// Address records are sorted by the length of the prefix they share
+    /// with the client address, so that the closest addresses come first.
+    /// Records at the same distance keep their order.
+    Sortlist(IpAddr),
+}
+
+/// Returns the number of leading bits `a` and `b` have in common, or `0` if
+/// they are not from the same address family.
+pub fn common_prefix_len(a: &IpAddr, b: &IpAddr) -> u32 {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => (u32::from(*a) ^ u32::from(*b)).leading_zeros(),
+        (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(*a) ^ u128::from(*b)).leading_zeros(),
+        _ => 0,
+    }
+}
@@ -4,6 +4,7 @@ use byteorder::{BigEndian, ByteOrder};
 use chomp::prelude::parse_only;
 
 use super::parser::*;
+use crate::compress::*;
 use crate::constants::*;
 use crate::errors::*;
 use crate::parsed_packet::*;
@@ -139,6 +140,41 @@ impl RR {
     pub fn rdata(&self) -> &[u8] {
         &self.packet[self.rdata_offset as usize..]
     }
+
+    /// Returns the record type.
+    pub fn rr_type(&self) -> u16 {
+        BigEndian::read_u16(&self.packet[self.header_offset() + DNS_RR_TYPE_OFFSET..])
+    }
+
+    /// Returns the TTL. Questions don't have a TTL.
+    pub fn ttl(&self) -> Option<u32> {
+        if self.is_question() {
+            return None;
+        }
+        Some(BigEndian::read_u32(
+            &self.packet[self.header_offset() + DNS_RR_TTL_OFFSET..],
+        ))
+    }
+
+    /// Changes the TTL. This is a no-op for questions.
+    pub fn set_ttl(&mut self, ttl: u32) {
+        if self.is_question() {
+            return;
+        }
+        let offset = self.header_offset() + DNS_RR_TTL_OFFSET;
+        BigEndian::write_u32(&mut self.packet[offset..], ttl)
+    }
+
+    #[inline]
+    fn is_question(&self) -> bool {
+        self.rdata_offset as usize - self.header_offset() == DNS_RR_QUESTION_HEADER_SIZE
+    }
+
+    /// Returns the location of the header, right after the name.
+    #[inline]
+    fn header_offset(&self) -> usize {
+        Compress::raw_name_len(&self.packet)
+    }
 }
 
 pub struct A;
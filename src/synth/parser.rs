@@ -1,6 +1,5 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::str::{self, FromStr};
-use std::u32;
 
 use chomp::ascii::*;
 use chomp::combinators::*;
@@ -0,0 +1,21 @@
+/// A `TruncationPolicy` describes how records are dropped by
+/// `ParsedPacket::truncate_to()`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TruncationPolicy {
+    /// Whether the records of an RRset must be dropped together, rather than
+    /// leaving a partial RRset in the packet. Records of the same RRset are
+    /// expected to be adjacent.
+    pub keep_rrsets: bool,
+}
+
+impl TruncationPolicy {
+    /// A policy dropping individual records.
+    pub fn records() -> Self {
+        TruncationPolicy { keep_rrsets: false }
+    }
+
+    /// A policy dropping whole RRsets.
+    pub fn rrsets() -> Self {
+        TruncationPolicy { keep_rrsets: true }
+    }
+}
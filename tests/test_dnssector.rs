@@ -1,7 +1,7 @@
-use dnssector;
-
 mod tests {
-    use super::dnssector::*;
+    use std::net::IpAddr;
+
+    use dnssector::*;
 
     #[test]
     fn test_empty_packet() {
@@ -17,7 +17,7 @@ mod tests {
         let ret = dns_sector.parse();
         assert!(ret.is_err());
 
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::PacketTooSmall => {}
             _ => panic!(),
         }
@@ -29,7 +29,7 @@ mod tests {
         let dns_sector = DNSSector::new(data_small).unwrap();
         let ret = dns_sector.parse();
         assert!(ret.is_err());
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::InvalidPacket(_) => {}
             a => panic!("type: {:?}", a),
         }
@@ -41,7 +41,7 @@ mod tests {
         let dns_sector = DNSSector::new(data_small).unwrap();
         let ret = dns_sector.parse();
         assert!(ret.is_err());
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::InternalError(_) => {}
             a => panic!("type: {:?}", a),
         }
@@ -61,7 +61,7 @@ mod tests {
         let dns_sector = DNSSector::new(data_small).unwrap();
         let ret = dns_sector.parse();
         assert!(ret.is_err());
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::PacketTooSmall => {}
             a => panic!("type: {:?}", a),
         }
@@ -78,7 +78,7 @@ mod tests {
         let dns_sector = DNSSector::new(data_small).unwrap();
         let ret = dns_sector.parse();
         assert!(ret.is_err());
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::InvalidName(_) => {}
             a => panic!("type: {:?}", a),
         }
@@ -104,7 +104,7 @@ mod tests {
         let dns_sector = DNSSector::new(data_small).unwrap();
         let ret = dns_sector.parse();
         assert!(ret.is_err());
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::InvalidName(_) => {}
             a => panic!("type: {:?}", a),
         }
@@ -149,7 +149,7 @@ mod tests {
         let dns_sector = DNSSector::new(data_small).unwrap();
         let ret = dns_sector.parse();
         assert!(ret.is_err());
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::InvalidName("Label length too long") => {}
             DSError::InvalidPacket("A question shouldn\'t also contain answers") => {}
             a => panic!("type: {:?}", a),
@@ -169,7 +169,7 @@ mod tests {
         let dns_sector = DNSSector::new(data).unwrap();
         let ret = dns_sector.parse();
         assert!(ret.is_err());
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::InvalidPacket("AAAA record doesn\'t include a 16 bytes IP address") => {}
             DSError::InvalidPacket("A question shouldn\'t also contain answers") => {}
             a => panic!("type: {:?}", a),
@@ -357,7 +357,7 @@ mod tests {
         let dns_sector = DNSSector::new(data).unwrap();
         let ret = dns_sector.parse();
         assert!(ret.is_err());
-        match ret.err().expect("error").downcast::<DSError>().unwrap() {
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
             DSError::InvalidPacket(_) => {}
             _ => panic!(),
         }
@@ -378,4 +378,2037 @@ mod tests {
         let ret = dns_sector.parse();
         assert!(ret.is_ok());
     }
+
+    #[test]
+    fn test_parse_with_zero_questions() {
+        // cookie-only query: no question, one OPT record
+        let data = vec![
+            0x12, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+            0x29, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x0a, 0x00, 0x08, 0x01,
+            0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        ];
+        let ret = DNSSector::new(data.clone()).unwrap().parse();
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
+            DSError::InvalidPacket(_) => {}
+            a => panic!("type: {:?}", a),
+        }
+        let mut parsed_packet = DNSSector::new(data)
+            .unwrap()
+            .parse_with(ParseOptions::permissive())
+            .expect("Cookie-only query couldn't be parsed");
+        assert!(parsed_packet.question().is_none());
+        assert!(parsed_packet.into_iter_question().is_none());
+        assert_eq!(parsed_packet.edns_count, 1);
+        assert_eq!(parsed_packet.max_payload(), 4096);
+    }
+
+    #[test]
+    fn test_parse_with_chaos_class() {
+        // version.bind. CH TXT
+        let data = vec![
+            0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, b'v',
+            b'e', b'r', b's', b'i', b'o', b'n', 0x04, b'b', b'i', b'n', b'd', 0x00, 0x00, 0x10,
+            0x00, 0x03,
+        ];
+        let ret = DNSSector::new(data.clone()).unwrap().parse();
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
+            DSError::UnsupportedClass(3) => {}
+            a => panic!("type: {:?}", a),
+        }
+        let options = ParseOptions::strict().with_allowed_classes(&[Class::IN, Class::CH]);
+        let mut parsed_packet = DNSSector::new(data)
+            .unwrap()
+            .parse_with(options)
+            .expect("CHAOS query couldn't be parsed");
+        let (name, rr_type, rr_class) = parsed_packet.question().unwrap();
+        assert_eq!(name, b"version.bind");
+        assert_eq!(rr_type, u16::from(Type::TXT));
+        assert_eq!(rr_class, u16::from(Class::CH));
+    }
+
+    #[test]
+    fn test_parse_with_multiple_questions() {
+        let data = vec![
+            0x00, 0x01, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, b'a',
+            0x00, 0x00, 0x01, 0x00, 0x01, 0x01, b'b', 0x00, 0x00, 0x1c, 0x00, 0x01,
+        ];
+        assert!(DNSSector::new(data.clone()).unwrap().parse().is_err());
+        let options = ParseOptions::strict().with_questions(1, 2);
+        let mut parsed_packet = DNSSector::new(data)
+            .unwrap()
+            .parse_with(options)
+            .expect("Packet with two questions couldn't be parsed");
+        let mut names = vec![];
+        let mut it = parsed_packet.into_iter_question();
+        while let Some(item) = it {
+            names.push(item.name());
+            it = item.next();
+        }
+        assert_eq!(names, vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(parsed_packet.parse_options, options);
+    }
+
+    #[test]
+    fn test_parse_with_records_in_notify() {
+        // NOTIFY example.com. SOA, carrying the new SOA in the answer section
+        let mut data = vec![
+            0x00, 0x01, 0x20, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x07, b'e',
+            b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00, 0x06, 0x00,
+            0x01, 0xc0, 0x0c, 0x00, 0x06, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x1e, 0x02,
+            b'n', b's', 0xc0, 0x0c, 0x02, b'h', b'm', 0xc0, 0x0c,
+        ];
+        data.extend(vec![0; 20]);
+        let ret = DNSSector::new(data.clone()).unwrap().parse();
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
+            DSError::InvalidPacket("A question shouldn't also contain answers") => {}
+            a => panic!("type: {:?}", a),
+        }
+        let options = ParseOptions::strict().with_records_in_queries(true);
+        let mut parsed_packet = DNSSector::new(data)
+            .unwrap()
+            .parse_with(options)
+            .expect("NOTIFY couldn't be parsed");
+        assert_eq!(parsed_packet.opcode(), 4);
+        assert!(parsed_packet.into_iter_answer().is_some());
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let mut data: Vec<u8> = vec![0, 0, 0x80, 0, 0, 1, 0, 2, 0, 0, 0, 0];
+
+        // query
+        data.extend(vec![1, b'a', 0, 0, 28, 0, 1]);
+
+        // 1st answer
+        data.extend(vec![0xc0, 12, 0, 28, 0, 1, 0, 0, 0, 0, 0, 16]);
+        data.extend(vec![0; 16]);
+
+        // 2nd answer
+        data.extend(vec![0xc0, 12, 0, 28, 0, 1, 0, 0, 0, 0, 0, 4, 1, 2, 3, 4]);
+
+        let ret = DNSSector::new(data.clone())
+            .unwrap()
+            .try_parse_with(ParseOptions::default());
+        let parse_error = ret.expect_err("error");
+        assert_eq!(
+            parse_error.kind,
+            DSError::InvalidPacket("AAAA record doesn't include a 16 bytes IP address")
+        );
+        // The RDLENGTH field of the 2nd answer
+        assert_eq!(parse_error.offset, 57);
+        assert_eq!(parse_error.section, Some(Section::Answer));
+        assert_eq!(parse_error.rr_index, Some(1));
+        assert_eq!(parse_error.rr_type, Some(Type::AAAA.into()));
+
+        let err = DNSSector::new(data).unwrap().parse().expect_err("error");
+        assert_eq!(ParseError::from_error(&err), Some(&parse_error));
+        match err.downcast::<DSError>().unwrap() {
+            DSError::InvalidPacket(_) => {}
+            a => panic!("type: {:?}", a),
+        }
+
+        // A record whose data doesn't fit in the packet
+        let mut data: Vec<u8> = vec![0, 0, 0x80, 0, 0, 1, 0, 1, 0, 0, 0, 0];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 1, 2]);
+        let parse_error = DNSSector::new(data)
+            .unwrap()
+            .try_parse_with(ParseOptions::default())
+            .expect_err("error");
+        assert_eq!(parse_error.kind, DSError::PacketTooSmall);
+        assert_eq!(parse_error.offset, 29);
+        assert_eq!(parse_error.rr_type, Some(Type::A.into()));
+    }
+
+    #[test]
+    fn test_parse_error_location_in_name() {
+        let mut data: Vec<u8> = vec![0, 0, 0x80, 0, 0, 1, 0, 0, 0, 1, 0, 0];
+
+        // query
+        data.extend(vec![0, 0, 1, 0, 1]);
+
+        // name server with a forward reference
+        data.extend(vec![1, b'a', 0xc0, 0x30, 0, 2, 0, 1, 0, 0, 0, 0, 0, 1, 0]);
+
+        let parse_error = DNSSector::new(data)
+            .unwrap()
+            .try_parse_with(ParseOptions::default())
+            .expect_err("error");
+        assert_eq!(
+            parse_error.kind,
+            DSError::InvalidName("Forward/self reference")
+        );
+        assert_eq!(parse_error.offset, 19);
+        assert_eq!(parse_error.section, Some(Section::NameServers));
+        assert_eq!(parse_error.rr_index, Some(0));
+        assert_eq!(parse_error.rr_type, Some(Type::NS.into()));
+    }
+
+    #[test]
+    fn test_parse_truncated_response() {
+        let mut data: Vec<u8> = vec![0, 0, 0x82, 0, 0, 1, 0, 3, 0, 0, 0, 1];
+
+        // query
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+
+        // 1st answer
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 1, 2, 3, 4]);
+
+        // 2nd answer
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 5, 6, 7, 8]);
+
+        // 3rd answer, cut in the middle of the address
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 9, 10]);
+
+        let ret = DNSSector::new(data.clone()).unwrap().parse();
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
+            DSError::PacketTooSmall => {}
+            a => panic!("type: {:?}", a),
+        }
+
+        let options = ParseOptions::default().with_recover_truncated(true);
+        let mut parsed_packet = DNSSector::new(data.clone())
+            .unwrap()
+            .parse_with(options)
+            .expect("Truncated packet couldn't be recovered");
+        assert!(parsed_packet.is_partial());
+        assert_eq!(
+            parsed_packet.dropped_records,
+            Some(DroppedRecords {
+                answers: 1,
+                nameservers: 0,
+                additional: 1,
+            })
+        );
+        assert_eq!(DNSSector::ancount(parsed_packet.packet()), 2);
+        assert_eq!(DNSSector::arcount(parsed_packet.packet()), 0);
+        assert_eq!(parsed_packet.packet().len(), 12 + 7 + 16 * 2);
+        let mut ips = vec![];
+        let mut it = parsed_packet.into_iter_answer();
+        while let Some(item) = it {
+            ips.push(item.rr_ip().unwrap());
+            it = item.next();
+        }
+        assert_eq!(
+            ips,
+            vec![IpAddr::from([1, 2, 3, 4]), IpAddr::from([5, 6, 7, 8])]
+        );
+        assert!(parsed_packet.into_iter_additional().is_none());
+
+        // The packet remains mutable
+        parsed_packet.into_iter_answer().unwrap().delete().unwrap();
+        assert_eq!(DNSSector::ancount(parsed_packet.packet()), 1);
+
+        // Recovery only applies to packets with the TC bit
+        data[2] = 0x80;
+        assert!(DNSSector::new(data).unwrap().parse_with(options).is_err());
+    }
+
+    #[test]
+    fn test_parse_truncated_response_drops_opt() {
+        let mut data: Vec<u8> = vec![0, 0, 0x82, 0, 0, 1, 0, 1, 0, 0, 0, 1];
+
+        // query
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+
+        // answer
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 1, 2, 3, 4]);
+
+        // OPT record, missing its options
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 8, 0, 10]);
+
+        let options = ParseOptions::default().with_recover_truncated(true);
+        let parsed_packet = DNSSector::new(data)
+            .unwrap()
+            .parse_with(options)
+            .expect("Truncated packet couldn't be recovered");
+        assert_eq!(parsed_packet.dropped_records.unwrap().total(), 1);
+        assert_eq!(parsed_packet.offset_edns, None);
+        assert_eq!(parsed_packet.ext_flags, None);
+        assert_eq!(parsed_packet.flags() & DNS_FLAG_DO, 0);
+        assert_eq!(parsed_packet.max_payload(), 512);
+    }
+    #[test]
+    fn test_parse_borrowed_packet() {
+        let mut data: Vec<u8> = vec![0xbe, 0xef, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 1];
+
+        // query
+        data.extend(vec![1, b'A', 0, 0, 1, 0, 1]);
+
+        // answers
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 5, 6, 7, 8]);
+
+        // OPT
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 0]);
+
+        let parsed_packet_ref = DNSSector::new(&data[..])
+            .unwrap()
+            .parse()
+            .expect("Valid packet couldn't be parsed");
+        assert!(matches!(
+            parsed_packet_ref.packet,
+            std::borrow::Cow::Borrowed(_)
+        ));
+        assert_eq!(parsed_packet_ref.tid(), 0xbeef);
+        assert!(parsed_packet_ref.is_response());
+        assert!(parsed_packet_ref.flags() & DNS_FLAG_DO != 0);
+        assert_eq!(parsed_packet_ref.rcode(), 0);
+        assert_eq!(parsed_packet_ref.max_payload(), 4096);
+        assert_eq!(parsed_packet_ref.question(), Some((b"a".to_vec(), 1, 1)));
+        assert_eq!(
+            parsed_packet_ref.question_raw(),
+            Some((vec![1, b'A'], 1, 1))
+        );
+        assert_eq!(parsed_packet_ref.iter_question().count(), 1);
+        let question = parsed_packet_ref.iter_question().next().unwrap();
+        assert_eq!(question.rr_type(), 1);
+        assert_eq!(question.rr_ttl(), None);
+        assert_eq!(question.rdata(), None);
+        assert!(question.rr_ip().is_err());
+        assert!(question.rr_rd().is_err());
+        let answers: Vec<_> = parsed_packet_ref
+            .iter_answer()
+            .map(|rr| (rr.name(), rr.rr_ttl().unwrap(), rr.rr_ip().unwrap()))
+            .collect();
+        assert_eq!(
+            answers,
+            vec![
+                (b"a".to_vec(), 42, IpAddr::from([1, 2, 3, 4])),
+                (b"a".to_vec(), 42, IpAddr::from([5, 6, 7, 8])),
+            ]
+        );
+        assert_eq!(parsed_packet_ref.iter_nameservers().count(), 0);
+        assert_eq!(parsed_packet_ref.iter_additional().count(), 0);
+        assert_eq!(parsed_packet_ref.iter_additional_including_opt().count(), 1);
+
+        // Same validation rules as owned packets
+        let mut bogus = data.clone();
+        bogus.push(0);
+        assert!(DNSSector::new(&bogus[..]).unwrap().parse().is_err());
+
+        // Converting into a `ParsedPacket` makes it mutable
+        let mut parsed_packet: ParsedPacket = parsed_packet_ref.into();
+        parsed_packet.into_iter_answer().unwrap().delete().unwrap();
+        assert_eq!(DNSSector::ancount(parsed_packet.packet()), 1);
+        assert_eq!(DNSSector::ancount(&data), 2);
+    }
+    fn response_with_rdata(rr_type: u16, rdata: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![0, 0, 0x81, 0, 0, 1, 0, 1, 0, 0, 0, 0];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        data.extend(vec![0xc0, 12]);
+        data.extend(rr_type.to_be_bytes());
+        data.extend(vec![0, 1, 0, 0, 0, 42]);
+        data.extend((rdata.len() as u16).to_be_bytes());
+        data.extend(rdata);
+        data
+    }
+
+    fn rdata_error(rr_type: Type, rdata: &[u8]) -> Option<DSError> {
+        DNSSector::new(response_with_rdata(rr_type.into(), rdata))
+            .unwrap()
+            .parse()
+            .err()
+            .map(|e| e.downcast::<DSError>().unwrap())
+    }
+
+    #[test]
+    fn test_rdata_validation_srv_naptr_txt() {
+        let srv = [0, 10, 0, 5, 0x14, 0x95, 3, b'w', b'w', b'w', 1, b'a', 0];
+        assert_eq!(rdata_error(Type::SRV, &srv), None);
+        let compressed_srv = [0, 10, 0, 5, 0x14, 0x95, 0xc0, 12];
+        assert!(matches!(
+            rdata_error(Type::SRV, &compressed_srv),
+            Some(DSError::InvalidName(_))
+        ));
+        let mut srv_with_extra_data = srv.to_vec();
+        srv_with_extra_data.push(0);
+        assert!(matches!(
+            rdata_error(Type::SRV, &srv_with_extra_data),
+            Some(DSError::InvalidPacket(_))
+        ));
+        assert!(rdata_error(Type::SRV, &[0, 10, 0, 5, 0x14, 0x95]).is_some());
+
+        let naptr = [
+            0, 1, 0, 2, 1, b'u', 7, b'E', b'2', b'U', b'+', b's', b'i', b'p', 0, 0,
+        ];
+        assert_eq!(rdata_error(Type::NAPTR, &naptr), None);
+        assert!(matches!(
+            rdata_error(Type::NAPTR, &naptr[..naptr.len() - 1]),
+            Some(DSError::InvalidPacket(_))
+        ));
+
+        assert_eq!(rdata_error(Type::TXT, &[2, b'h', b'i', 0]), None);
+        assert!(matches!(
+            rdata_error(Type::TXT, &[3, b'h', b'i']),
+            Some(DSError::InvalidPacket(_))
+        ));
+        assert_eq!(rdata_error(Type::TXT, &[]), None);
+    }
+
+    #[test]
+    fn test_rdata_validation_dnssec() {
+        let mut rrsig = vec![0, 1, 13, 1, 0, 0, 0, 42, 0, 0, 0, 2, 0, 0, 0, 1, 0x12, 0x34];
+        rrsig.extend(vec![1, b'a', 0, 0xaa, 0xbb]);
+        assert_eq!(rdata_error(Type::RRSIG, &rrsig), None);
+        assert!(rdata_error(Type::RRSIG, &rrsig[..18]).is_some());
+
+        let nsec = [1, b'b', 0, 0, 1, 0x40, 1, 1, 0x40];
+        assert_eq!(rdata_error(Type::NSEC, &nsec), None);
+        let unordered_windows = [1, b'b', 0, 1, 1, 0x40, 0, 1, 0x40];
+        assert!(matches!(
+            rdata_error(Type::NSEC, &unordered_windows),
+            Some(DSError::InvalidPacket(_))
+        ));
+        let empty_window = [1, b'b', 0, 0, 0];
+        assert!(rdata_error(Type::NSEC, &empty_window).is_some());
+        let truncated_window = [1, b'b', 0, 0, 2, 0x40];
+        assert!(rdata_error(Type::NSEC, &truncated_window).is_some());
+
+        let nsec3 = [1, 0, 0, 10, 2, 0xab, 0xcd, 2, 0x11, 0x22, 0, 1, 0x40];
+        assert_eq!(rdata_error(Type::NSEC3, &nsec3), None);
+        let nsec3_bad_salt = [1, 0, 0, 10, 9, 0xab, 0xcd, 2, 0x11, 0x22];
+        assert!(rdata_error(Type::NSEC3, &nsec3_bad_salt).is_some());
+        let nsec3_no_hash = [1, 0, 0, 10, 2, 0xab, 0xcd, 0];
+        assert!(rdata_error(Type::NSEC3, &nsec3_no_hash).is_some());
+
+        assert_eq!(rdata_error(Type::DS, &[0x12, 0x34, 13, 2, 0xaa]), None);
+        assert!(rdata_error(Type::DS, &[0x12, 0x34, 13, 2]).is_some());
+    }
+
+    #[test]
+    fn test_rdata_validation_svcb_caa_tlsa() {
+        let mut https = vec![0, 1, 0];
+        https.extend(vec![0, 1, 0, 3, 2, b'h', b'2']); // alpn
+        https.extend(vec![0, 3, 0, 2, 1, 0xbb]); // port
+        https.extend(vec![0, 4, 0, 4, 192, 0, 2, 1]); // ipv4hint
+        assert_eq!(rdata_error(Type::HTTPS, &https), None);
+
+        let mut unordered_keys = vec![0, 1, 0];
+        unordered_keys.extend(vec![0, 3, 0, 2, 1, 0xbb]);
+        unordered_keys.extend(vec![0, 1, 0, 3, 2, b'h', b'2']);
+        assert!(matches!(
+            rdata_error(Type::HTTPS, &unordered_keys),
+            Some(DSError::InvalidPacket(_))
+        ));
+        let bad_port = [0, 1, 0, 0, 3, 0, 3, 1, 0xbb, 0];
+        assert!(rdata_error(Type::SVCB, &bad_port).is_some());
+        let empty_alpn = [0, 1, 0, 0, 1, 0, 1, 0];
+        assert!(rdata_error(Type::SVCB, &empty_alpn).is_some());
+        let overflowing_value = [0, 1, 0, 0, 4, 0, 8, 192, 0, 2, 1];
+        assert!(rdata_error(Type::SVCB, &overflowing_value).is_some());
+
+        let caa = [
+            0, 5, b'i', b's', b's', b'u', b'e', b'c', b'a', b'.', b'n', b'e', b't',
+        ];
+        assert_eq!(rdata_error(Type::CAA, &caa), None);
+        assert!(rdata_error(Type::CAA, &[0, 0, b'x']).is_some());
+        assert!(rdata_error(Type::CAA, &[0, 5, b'i', b's']).is_some());
+        assert!(rdata_error(Type::CAA, &[0, 1, b'-']).is_some());
+
+        assert_eq!(rdata_error(Type::TLSA, &[3, 1, 1, 0xaa]), None);
+        assert!(rdata_error(Type::TLSA, &[3, 1]).is_some());
+    }
+    #[test]
+    fn test_parse_limits() {
+        let mut data: Vec<u8> = vec![0, 0, 0x81, 0, 0, 1, 0, 2, 0, 0, 0, 1];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+
+        // The second answer uses a pointer to the name of the first one
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+        data.extend(vec![0xc0, 19, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 5, 6, 7, 8]);
+
+        // OPT with two options
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 8]);
+        data.extend(vec![0, 10, 0, 0, 0, 12, 0, 0]);
+
+        let limit_error = |limits: Limits| {
+            let options = ParseOptions::default().with_limits(limits);
+            match DNSSector::new(data.clone()).unwrap().parse_with(options) {
+                Ok(_) => None,
+                Err(e) => match e.downcast::<DSError>().unwrap() {
+                    DSError::LimitExceeded(reason) => Some(reason),
+                    a => panic!("type: {:?}", a),
+                },
+            }
+        };
+        assert_eq!(limit_error(Limits::default()), None);
+        assert_eq!(
+            limit_error(Limits::default().with_max_packet_size(data.len() - 1)),
+            Some("Packet too large")
+        );
+        let options = ParseOptions::default()
+            .with_limits(Limits::default().with_max_packet_size(data.len() - 1));
+        let parse_error = DNSSector::new(data.clone())
+            .unwrap()
+            .try_parse_with(options)
+            .expect_err("error");
+        assert_eq!(parse_error.offset, 0);
+        assert_eq!(
+            limit_error(Limits::default().with_max_rrs_per_section(1)),
+            Some("Too many records in a section")
+        );
+        assert_eq!(
+            limit_error(Limits::default().with_max_rrs(3)),
+            Some("Too many records")
+        );
+        assert_eq!(
+            limit_error(Limits::default().with_max_edns_options(1)),
+            Some("Too many edns options")
+        );
+        assert_eq!(
+            limit_error(Limits::default().with_max_indirections(1)),
+            Some("Too many indirections")
+        );
+        assert_eq!(
+            limit_error(Limits::default().with_max_indirections(2)),
+            None
+        );
+
+        // The number of indirections can also be raised above the default
+        let mut chain: Vec<u8> = vec![1, b'a', 0];
+        let mut prev = 0;
+        for _ in 0..=DNS_MAX_HOSTNAME_INDIRECTIONS {
+            let offset = chain.len();
+            chain.extend(vec![1, b'a', 0xc0, prev]);
+            prev = offset as u8;
+        }
+        let indirections = DNS_MAX_HOSTNAME_INDIRECTIONS + 1;
+        match Compress::check_compressed_name(&chain, prev as usize)
+            .expect_err("error")
+            .downcast::<DSError>()
+            .unwrap()
+        {
+            DSError::InvalidName(reason) => assert_eq!(reason, "Too many indirections"),
+            a => panic!("type: {:?}", a),
+        }
+        assert!(Compress::check_compressed_name_with_max_indirections(
+            &chain,
+            prev as usize,
+            Limits::default()
+                .with_max_indirections(indirections)
+                .max_indirections
+        )
+        .is_ok());
+
+        // Modifications are bounded by the same limits
+        let options = ParseOptions::default()
+            .with_limits(Limits::default().with_max_packet_size(data.len() + 16));
+        let mut parsed_packet = DNSSector::new(data.clone())
+            .unwrap()
+            .parse_with(options)
+            .unwrap();
+        let ret = parsed_packet.insert_rr_from_string(Section::Answer, "a. 42 IN A 9.9.9.9");
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
+            DSError::LimitExceeded(_) => {}
+            a => panic!("type: {:?}", a),
+        }
+
+        // Large packets are accepted by default, e.g. for TCP
+        let txt_response = |rdlen: usize| {
+            let mut data: Vec<u8> = vec![0, 0, 0x81, 0, 0, 1, 0, 1, 0, 0, 0, 0];
+            data.extend(vec![1, b'a', 0, 0, 16, 0, 1]);
+            data.extend(vec![0xc0, 12, 0, 16, 0, 1, 0, 0, 0, 0]);
+            data.extend(vec![(rdlen >> 8) as u8, rdlen as u8]);
+            for _ in 0..rdlen / 256 {
+                data.push(255);
+                data.extend(vec![b'x'; 255]);
+            }
+            let rem = rdlen % 256;
+            if rem > 0 {
+                data.push((rem - 1) as u8);
+                data.extend(vec![b'x'; rem - 1]);
+            }
+            data
+        };
+        let large = txt_response(10000);
+        assert!(large.len() > DNS_MAX_UNCOMPRESSED_SIZE);
+        let options = ParseOptions::default()
+            .with_limits(Limits::default().with_max_packet_size(DNS_MAX_UNCOMPRESSED_SIZE));
+        assert!(DNSSector::new(large.clone())
+            .unwrap()
+            .parse_with(options)
+            .is_err());
+        let mut parsed_packet = DNSSector::new(large).unwrap().parse().unwrap();
+        parsed_packet
+            .insert_rr_from_string(Section::Answer, "a. 42 IN A 9.9.9.9")
+            .unwrap();
+        assert!(parsed_packet.packet().len() > DNS_MAX_UNCOMPRESSED_SIZE);
+
+        // Packets can't grow beyond DNS_MAX_PACKET_SIZE
+        let mut parsed_packet = DNSSector::new(txt_response(65499))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(parsed_packet.packet().len(), 65530);
+        let ret = parsed_packet.insert_rr_from_string(Section::Answer, "a. 42 IN A 9.9.9.9");
+        match ret.expect_err("error").downcast::<DSError>().unwrap() {
+            DSError::PacketTooLarge => {}
+            a => panic!("type: {:?}", a),
+        }
+    }
+    #[test]
+    fn test_parse_lazy() {
+        let mut data: Vec<u8> = vec![0, 0, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 1];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 0]);
+
+        let mut parsed_packet = DNSSector::new(data.clone())
+            .unwrap()
+            .parse_lazy()
+            .expect("Valid packet couldn't be parsed");
+        assert!(!parsed_packet.is_validated());
+        assert_eq!(parsed_packet.question(), Some((b"a".to_vec(), 1, 1)));
+        assert_eq!(parsed_packet.max_payload(), 4096);
+        assert!(parsed_packet.flags() & DNS_FLAG_DO != 0);
+        let item = parsed_packet.into_iter_answer().unwrap();
+        assert_eq!(item.rr_ip().unwrap(), IpAddr::from([1, 2, 3, 4]));
+        assert!(parsed_packet.is_validated());
+
+        // Invalid records are only detected once the sections are needed
+        let mut bogus = data.clone();
+        bogus[20] = 40;
+        let mut parsed_packet = DNSSector::new(bogus.clone())
+            .unwrap()
+            .parse_lazy()
+            .expect("Header and question couldn't be parsed");
+        assert_eq!(parsed_packet.tid(), 0);
+        assert!(parsed_packet.validate().is_err());
+        assert!(parsed_packet.try_into_iter_answer().is_err());
+        assert!(parsed_packet.try_into_iter_additional().is_err());
+        assert!(parsed_packet.min_ttl().is_err());
+        assert!(parsed_packet
+            .insert_rr_from_string(Section::Answer, "a. 42 IN A 5.6.7.8")
+            .is_err());
+        assert!(!parsed_packet.is_validated());
+        assert!(DNSSector::new(bogus.clone()).unwrap().parse().is_err());
+
+        // Iterating over a malformed section doesn't panic, and the error
+        // is still reported afterwards
+        let mut parsed_packet = DNSSector::new(bogus).unwrap().parse_lazy().unwrap();
+        assert!(parsed_packet.into_iter_answer().is_none());
+        assert!(parsed_packet.into_iter_nameservers().is_none());
+        assert!(parsed_packet.into_iter_additional().is_none());
+        assert!(parsed_packet.into_iter_additional_including_opt().is_none());
+        assert!(!parsed_packet.is_validated());
+        assert!(parsed_packet.validate().is_err());
+
+        // Structural errors are still detected up front
+        let mut bogus = data.clone();
+        bogus.push(0);
+        assert!(DNSSector::new(bogus).unwrap().parse_lazy().is_err());
+        let mut bogus = data.clone();
+        bogus[14] = 0x40;
+        assert!(DNSSector::new(bogus).unwrap().parse_lazy().is_err());
+    }
+    #[test]
+    fn test_rename_with_raw_names() {
+        let mut data: Vec<u8> = vec![0, 0, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+
+        let mut parsed_packet = DNSSector::new(data).unwrap().parse().unwrap();
+        assert_eq!(
+            parsed_packet.question_raw0(),
+            Some((&[1, b'a', 0][..], 1, 1))
+        );
+        parsed_packet
+            .rename_with_raw_names(&[1, b'b', 0], &[1, b'a', 0], false)
+            .unwrap();
+        assert_eq!(
+            parsed_packet.question_raw0(),
+            Some((&[1, b'b', 0][..], 1, 1))
+        );
+        let item = parsed_packet.into_iter_answer().unwrap();
+        assert_eq!(item.name(), b"b".to_vec());
+        assert_eq!(item.rr_ip().unwrap(), IpAddr::from([1, 2, 3, 4]));
+    }
+    #[test]
+    fn test_stream_codec() {
+        let mut query1: Vec<u8> = vec![0x12, 0x34, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        query1.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        let mut query2 = query1.clone();
+        query2[1] = 0x35;
+
+        let codec = StreamCodec::new(Transport::Tcp);
+        let mut stream = vec![];
+        for query in [&query1, &query2] {
+            let parsed_packet = DNSSector::new(query.clone()).unwrap().parse().unwrap();
+            codec.encode(&parsed_packet, &mut stream).unwrap();
+        }
+        assert_eq!(stream.len(), 2 * (2 + query1.len()));
+        assert_eq!(&stream[..2], &[0, query1.len() as u8]);
+
+        // Messages split across reads
+        let mut codec = StreamCodec::new(Transport::Tls);
+        let mut tids = vec![];
+        for chunk in stream.chunks(3) {
+            codec.feed(chunk);
+            while let Some(parsed_packet) = codec.next_parsed_packet().unwrap() {
+                tids.push(parsed_packet.tid());
+            }
+        }
+        assert_eq!(tids, vec![0x1234, 0x1235]);
+        assert_eq!(codec.buffered_len(), 0);
+
+        // Incomplete message
+        codec.feed(&stream[..5]);
+        assert!(codec.next_message().unwrap().is_none());
+        assert_eq!(codec.buffered_len(), 5);
+
+        // Size limit
+        let limits = Limits::default().with_max_packet_size(16);
+        let mut codec = StreamCodec::new(Transport::Tcp)
+            .with_parse_options(ParseOptions::default().with_limits(limits));
+        codec.feed(&stream);
+        match codec
+            .next_message()
+            .expect_err("error")
+            .downcast::<DSError>()
+            .unwrap()
+        {
+            DSError::LimitExceeded(_) => {}
+            a => panic!("type: {:?}", a),
+        }
+        assert!(codec.next_message().is_err());
+    }
+
+    #[test]
+    fn test_stream_codec_doq() {
+        let mut query: Vec<u8> = vec![0, 0, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        let mut parsed_packet = DNSSector::new(query).unwrap().parse().unwrap();
+
+        let mut codec = StreamCodec::new(Transport::Quic);
+        let mut stream = vec![];
+        codec.encode(&parsed_packet, &mut stream).unwrap();
+        codec.feed(&stream);
+        let decoded = codec.next_parsed_packet().unwrap().unwrap();
+        assert_eq!(decoded.packet(), parsed_packet.packet());
+
+        parsed_packet.set_tid(1);
+        assert!(codec.encode(&parsed_packet, &mut vec![]).is_err());
+        let mut stream = vec![];
+        StreamCodec::new(Transport::Tcp)
+            .encode(&parsed_packet, &mut stream)
+            .unwrap();
+        codec.feed(&stream);
+        match codec
+            .next_message()
+            .expect_err("error")
+            .downcast::<DSError>()
+            .unwrap()
+        {
+            DSError::InvalidPacket(_) => {}
+            a => panic!("type: {:?}", a),
+        }
+        assert_eq!(codec.buffered_len(), 0);
+    }
+    #[test]
+    fn test_additional_opt_before_other_records() {
+        let mut data: Vec<u8> = vec![0, 0, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 3];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 5, 6, 7, 8]);
+
+        let mut parsed_packet = DNSSector::new(data).unwrap().parse().unwrap();
+        let mut ips = vec![];
+        let mut it = parsed_packet.into_iter_additional();
+        while let Some(item) = it {
+            ips.push(item.rr_ip().unwrap());
+            it = item.next();
+        }
+        assert_eq!(
+            ips,
+            vec![IpAddr::from([1, 2, 3, 4]), IpAddr::from([5, 6, 7, 8])]
+        );
+    }
+    #[test]
+    fn test_compress_round_trip() {
+        let mut data: Vec<u8> = vec![0, 0, 0x81, 0x80, 0, 1, 0, 3, 0, 0, 0, 0];
+        data.extend(vec![1, b'a', 1, b'b', 0, 0, 1, 0, 1]);
+        data.extend(vec![
+            1, b'a', 1, b'b', 0, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4,
+        ]);
+        data.extend(vec![
+            1, b'c', 1, b'd', 1, b'e', 0, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4,
+        ]);
+        data.extend(vec![5, 6, 7, 8]);
+        data.extend(vec![
+            1, b'x', 1, b'd', 1, b'e', 0, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4,
+        ]);
+        data.extend(vec![9, 10, 11, 12]);
+
+        let compressed = Compress::compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(Compress::uncompress(&compressed).unwrap(), data);
+    }
+    #[test]
+    fn test_validate_response_for() {
+        let mut query_data: Vec<u8> = vec![0x12, 0x34, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        query_data.extend(vec![3, b'f', b'O', b'o', 0, 0, 1, 0, 1]);
+        let mut response_data = query_data.clone();
+        response_data[2] |= 0x80;
+        response_data[14] = b'o';
+        let parse = |data: &[u8]| DNSSector::new(data.to_vec()).unwrap().parse().unwrap();
+
+        let mut query = parse(&query_data);
+        let mut response = parse(&response_data);
+        assert_eq!(
+            response.validate_response_for(&query, MatchPolicy::default()),
+            Ok(())
+        );
+        assert_eq!(
+            response.validate_response_for(&query, MatchPolicy::exact_case()),
+            Err(ResponseMismatch::QuestionName)
+        );
+        assert_eq!(
+            query.validate_response_for(&response, MatchPolicy::default()),
+            Err(ResponseMismatch::NotAResponse)
+        );
+
+        let mismatch = |offset: usize, value: u8, query: &ParsedPacket| {
+            let mut data = response_data.clone();
+            data[offset] = value;
+            parse(&data).validate_response_for(query, MatchPolicy::default())
+        };
+        assert_eq!(mismatch(1, 0x35, &query), Err(ResponseMismatch::Tid));
+        assert_eq!(mismatch(2, 0xa9, &query), Err(ResponseMismatch::Opcode));
+        assert_eq!(
+            mismatch(13, b'x', &query),
+            Err(ResponseMismatch::QuestionName)
+        );
+        assert_eq!(
+            mismatch(18, 28, &query),
+            Err(ResponseMismatch::QuestionType)
+        );
+
+        let chaos = ParseOptions::default().with_allowed_classes(&[Class::IN, Class::CH]);
+        let mut data = response_data.clone();
+        data[20] = 3;
+        let mut response = DNSSector::new(data).unwrap().parse_with(chaos).unwrap();
+        assert_eq!(
+            response.validate_response_for(&query, MatchPolicy::default()),
+            Err(ResponseMismatch::QuestionClass)
+        );
+
+        let mut data = response_data[..12].to_vec();
+        data[5] = 0;
+        let options = ParseOptions::default().with_questions(0, 1);
+        let mut response = DNSSector::new(data).unwrap().parse_with(options).unwrap();
+        assert_eq!(
+            response.validate_response_for(&query, MatchPolicy::default()),
+            Err(ResponseMismatch::QuestionCount)
+        );
+    }
+    #[test]
+    fn test_question_case_randomization() {
+        let mut data: Vec<u8> = vec![0x12, 0x34, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        data.extend(b"\x10abcdefghijklmnop\x07Example\x03com\x00");
+        data.extend(vec![0, 1, 0, 1]);
+        let mut query = DNSSector::new(data.clone()).unwrap().parse().unwrap();
+        let original_name = query.randomize_question_case().unwrap();
+        assert_eq!(&original_name[..], &data[12..data.len() - 4]);
+        let (randomized_name, _, _) = query.question_raw0().unwrap();
+        let randomized_name = randomized_name.to_vec();
+        assert!(randomized_name.eq_ignore_ascii_case(&original_name));
+        assert_ne!(randomized_name, original_name);
+        assert_eq!(query.question().unwrap().0, b"abcdefghijklmnop.example.com");
+
+        // The response has to echo the exact case
+        let mut response = DNSSector::new(query.packet().to_vec())
+            .unwrap()
+            .parse()
+            .unwrap();
+        response.set_response(true);
+        assert!(response.echoes_question_case(&randomized_name));
+        assert!(!response.echoes_question_case(&original_name));
+        assert_eq!(
+            response.validate_response_for(&query, MatchPolicy::exact_case()),
+            Ok(())
+        );
+        response
+            .insert_rr_from_string(
+                Section::Answer,
+                "ABCDEFGHIJKLMNOP.EXAMPLE.COM. 60 IN A 192.0.2.1",
+            )
+            .unwrap();
+        response
+            .insert_rr_from_string(Section::Answer, "other.example.com. 60 IN A 192.0.2.2")
+            .unwrap();
+
+        // Restoring the original case
+        response.restore_question_case(&original_name).unwrap();
+        assert!(response.echoes_question_case(&original_name));
+        let mut names = vec![];
+        let mut it = response.into_iter_answer();
+        while let Some(item) = it {
+            let mut name = vec![];
+            item.copy_raw_name(&mut name);
+            names.push(name);
+            it = item.next();
+        }
+        assert_eq!(names[0], original_name);
+        assert!(names[1].eq_ignore_ascii_case(b"\x05other\x07example\x03com\x00"));
+    }
+    #[test]
+    fn test_typed_header() {
+        let mut data: Vec<u8> = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        // OPT, without the DO flag
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        let mut parsed_packet = DNSSector::new(data.clone()).unwrap().parse().unwrap();
+        assert_eq!(parsed_packet.typed_opcode(), Opcode::QUERY);
+        assert_eq!(parsed_packet.typed_rcode(), Rcode::NOERROR);
+        assert!(parsed_packet.rd());
+        assert!(!parsed_packet.aa() && !parsed_packet.tc() && !parsed_packet.ra());
+        assert!(!parsed_packet.ad() && !parsed_packet.cd() && !parsed_packet.z());
+        assert!(!parsed_packet.dnssec_ok());
+
+        parsed_packet.set_rd(false);
+        parsed_packet.set_aa(true);
+        parsed_packet.set_cd(true);
+        parsed_packet.set_z(true);
+        parsed_packet.set_dnssec_ok(true).unwrap();
+        parsed_packet.set_typed_opcode(Opcode::NOTIFY);
+        parsed_packet.set_typed_rcode(Rcode::BADCOOKIE).unwrap();
+        assert_eq!(parsed_packet.extended_rcode(), 23);
+        assert_eq!(parsed_packet.rcode(), 7);
+        assert_eq!(parsed_packet.ext_rcode, Some(1));
+
+        // Everything is stored in the packet
+        let parsed_packet_ref = DNSSector::new(parsed_packet.packet())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(parsed_packet_ref.typed_opcode(), Opcode::NOTIFY);
+        assert_eq!(parsed_packet_ref.typed_rcode(), Rcode::BADCOOKIE);
+        assert!(parsed_packet_ref.flag(Flag::DO));
+        assert!(parsed_packet_ref.flag(Flag::AA));
+        assert!(parsed_packet_ref.flag(Flag::CD));
+        assert!(parsed_packet_ref.flag(Flag::Z));
+        assert!(!parsed_packet_ref.flag(Flag::RD));
+        assert!(!parsed_packet_ref.flag(Flag::QR));
+
+        // Unknown values round-trip
+        parsed_packet.set_typed_opcode(Opcode::Unknown(12));
+        assert_eq!(parsed_packet.typed_opcode(), Opcode::Unknown(12));
+        parsed_packet.set_extended_rcode(0x123).unwrap();
+        assert_eq!(parsed_packet.typed_rcode(), Rcode::Unknown(0x123));
+        assert!(parsed_packet.set_extended_rcode(0x1000).is_err());
+
+        // Without an OPT record, only header values can be set
+        let mut data = data[..data.len() - 11].to_vec();
+        data[11] = 0;
+        let mut parsed_packet = DNSSector::new(data).unwrap().parse().unwrap();
+        assert!(parsed_packet.set_dnssec_ok(true).is_err());
+        assert!(parsed_packet.set_dnssec_ok(false).is_ok());
+        assert!(parsed_packet.set_typed_rcode(Rcode::BADVERS).is_err());
+        parsed_packet.set_typed_rcode(Rcode::REFUSED).unwrap();
+        assert_eq!(parsed_packet.typed_rcode(), Rcode::REFUSED);
+        assert_eq!(u8::from(Rcode::REFUSED), 5);
+        assert_eq!(u8::from(Rcode::BADVERS), 0);
+        assert_eq!(u16::from(Rcode::BADVERS), 16);
+    }
+    #[test]
+    fn test_to_response() {
+        let mut data: Vec<u8> = vec![0x12, 0x34, 0x01, 0x30, 0, 1, 0, 0, 0, 0, 0, 1];
+        data.extend(b"\x07ExAmPlE\x03cOm\x00");
+        data.extend(vec![0, 1, 0, 1]);
+        // OPT with the DO flag and a cookie
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 12]);
+        data.extend(vec![0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8]);
+        let query = DNSSector::new(data.clone()).unwrap().parse().unwrap();
+
+        let options = ResponseOptions::default()
+            .with_rcode(Rcode::NXDOMAIN)
+            .with_authoritative(true)
+            .with_max_payload(1400);
+        let mut response = query.to_response(options).unwrap();
+        assert!(response.is_response());
+        assert_eq!(response.tid(), 0x1234);
+        assert_eq!(response.typed_opcode(), Opcode::QUERY);
+        assert_eq!(response.typed_rcode(), Rcode::NXDOMAIN);
+        assert!(response.rd() && response.cd() && response.aa());
+        assert!(!response.ra() && !response.ad() && !response.tc());
+        assert!(response.dnssec_ok());
+        assert_eq!(response.max_payload(), 1400);
+        assert_eq!(response.edns_count, 0);
+        assert_eq!(&response.packet()[12..29], &data[12..29]);
+        response
+            .insert_rr_from_string(Section::Answer, "example.com. 60 IN A 192.0.2.1")
+            .unwrap();
+        assert_eq!(DNSSector::ancount(response.packet()), 1);
+
+        // The consuming variant reuses the query
+        let options = ResponseOptions::default()
+            .with_rcode(Rcode::BADVERS)
+            .with_recursion_available(true);
+        let response = query.into_response(options).unwrap();
+        assert_eq!(response.typed_rcode(), Rcode::BADVERS);
+        assert_eq!(response.rcode(), 0);
+        assert!(response.ra());
+        assert_eq!(
+            response.max_payload(),
+            DNS_DEFAULT_EDNS_PAYLOAD_SIZE as usize
+        );
+
+        // No OPT record in the query, no OPT record in the response
+        let mut data = data[..29].to_vec();
+        data[11] = 0;
+        let query = DNSSector::new(data).unwrap().parse().unwrap();
+        let response = query.to_response(ResponseOptions::default()).unwrap();
+        assert_eq!(response.offset_edns, None);
+        assert_eq!(DNSSector::arcount(response.packet()), 0);
+        assert_eq!(response.packet().len(), 29);
+        let options = ResponseOptions::default().with_rcode(Rcode::BADCOOKIE);
+        assert!(query.to_response(options).is_err());
+    }
+    #[test]
+    fn test_negative_responses() {
+        let mut data: Vec<u8> = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1];
+        data.extend(b"\x07blocked\x07example\x00");
+        data.extend(vec![0, 1, 0, 1]);
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        let query = DNSSector::new(data.clone()).unwrap().parse().unwrap();
+        let soa = || {
+            gen::RR::from_string(
+                "example. 3600 IN SOA ns.example. hostmaster.example. (1 7200 900 86400 300)",
+            )
+            .unwrap()
+        };
+
+        let options = ResponseOptions::default().with_extended_error(ExtendedError::BLOCKED);
+        let mut response = query.to_nxdomain(soa(), options).unwrap();
+        assert_eq!(response.typed_rcode(), Rcode::NXDOMAIN);
+        assert_eq!(DNSSector::ancount(response.packet()), 0);
+        assert_eq!(DNSSector::nscount(response.packet()), 1);
+        assert_eq!(response.edns_count, 1);
+        {
+            let it = response.into_iter_nameservers().unwrap();
+            assert_eq!(it.rr_type(), Type::SOA.into());
+            assert_eq!(it.rr_ttl(), 300);
+        }
+        {
+            let it = response.into_iter_edns().unwrap();
+            let raw = it.raw();
+            assert_eq!(&raw.packet[raw.offset..], &[0, 15, 0, 2, 0, 15]);
+        }
+        let reparsed = DNSSector::new(response.into_packet()).unwrap().parse();
+        assert!(reparsed.is_ok());
+
+        // The SOA TTL is kept if it is lower than MINIMUM
+        let mut soa_short = soa();
+        soa_short.set_ttl(60);
+        let mut response = query
+            .to_nodata(
+                soa_short,
+                ResponseOptions::default().with_rcode(Rcode::SERVFAIL),
+            )
+            .unwrap();
+        assert_eq!(response.typed_rcode(), Rcode::NOERROR);
+        assert_eq!(response.edns_count, 0);
+        assert_eq!(response.into_iter_nameservers().unwrap().rr_ttl(), 60);
+
+        // Only SOA records are accepted
+        let a = gen::RR::from_string("example. 3600 IN A 192.0.2.1").unwrap();
+        assert!(query.to_nxdomain(a, ResponseOptions::default()).is_err());
+    }
+    #[test]
+    fn test_truncate_to() {
+        let mut data: Vec<u8> = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1];
+        data.extend(b"\x07example\x00");
+        data.extend(vec![0, 1, 0, 1]);
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        let query = DNSSector::new(data).unwrap().parse().unwrap();
+        let response = || {
+            let mut response = query.to_response(ResponseOptions::default()).unwrap();
+            for i in 1..=4 {
+                let rr = format!("a.example. 60 IN A 192.0.2.{}", i);
+                response
+                    .insert_rr_from_string(Section::Answer, &rr)
+                    .unwrap();
+            }
+            for i in 1..=4 {
+                let rr = format!("b.example. 60 IN A 192.0.2.{}", i);
+                response
+                    .insert_rr_from_string(Section::Answer, &rr)
+                    .unwrap();
+            }
+            response
+                .insert_rr_from_string(Section::NameServers, "example. 60 IN NS ns.example.")
+                .unwrap();
+            for i in 1..=2 {
+                let rr = format!("ns.example. 60 IN A 192.0.2.{}", i);
+                response
+                    .insert_rr_from_string(Section::Additional, &rr)
+                    .unwrap();
+            }
+            response
+        };
+        let rr_len = 11 + 10 + 4;
+        let full_len = response().packet().len();
+
+        // Only additional records are dropped; the OPT record moves
+        let mut truncated = response();
+        let dropped = truncated
+            .truncate_to(full_len - 1, TruncationPolicy::default())
+            .unwrap();
+        assert_eq!(dropped.additional, 1);
+        assert_eq!(dropped.total(), 1);
+        assert!(!truncated.tc());
+        assert_eq!(truncated.packet().len(), full_len - (rr_len + 1));
+        assert_eq!(DNSSector::arcount(truncated.packet()), 2);
+        let mut reparsed = DNSSector::new(truncated.into_packet())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            reparsed.max_payload(),
+            DNS_DEFAULT_EDNS_PAYLOAD_SIZE as usize
+        );
+        assert!(reparsed.into_iter_additional().is_some());
+
+        // Answers are dropped last, and set TC
+        let mut truncated = response();
+        let max_len = full_len - 2 * (rr_len + 1) - 22 - rr_len;
+        let dropped = truncated
+            .truncate_to(max_len, TruncationPolicy::records())
+            .unwrap();
+        assert_eq!(dropped.additional, 2);
+        assert_eq!(dropped.nameservers, 1);
+        assert_eq!(dropped.answers, 1);
+        assert!(truncated.tc());
+        assert!(truncated.packet().len() <= max_len);
+        assert_eq!(truncated.offset_nameservers, None);
+        assert_eq!(
+            truncated.offset_additional,
+            truncated.offset_edns.map(|x| x - 11)
+        );
+        assert_eq!(truncated.edns_count, 0);
+        assert!(truncated.dnssec_ok() == query.dnssec_ok());
+        let mut it = truncated.into_iter_answer();
+        let mut count = 0;
+        while let Some(item) = it {
+            count += 1;
+            it = item.next();
+        }
+        assert_eq!(count, 7);
+        DNSSector::new(truncated.into_packet())
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        // Whole RRsets are dropped
+        let mut truncated = response();
+        let dropped = truncated
+            .truncate_to(max_len, TruncationPolicy::rrsets())
+            .unwrap();
+        assert_eq!(dropped.answers, 4);
+        assert_eq!(DNSSector::ancount(truncated.packet()), 4);
+
+        // Not even the question fits
+        let mut truncated = response();
+        assert!(truncated
+            .truncate_to(35, TruncationPolicy::default())
+            .is_err());
+        assert_eq!(truncated.packet().len(), full_len);
+    }
+
+    #[test]
+    fn test_truncate_compressed() {
+        let mut data: Vec<u8> = vec![0xbe, 0xef, 0x81, 0x80, 0, 1, 0, 3, 0, 0, 0, 1];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 5, 6, 7, 8]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 9, 9, 9, 9]);
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 4, 0, 12, 0, 0]);
+        let mut parsed_packet = DNSSector::new(data.clone()).unwrap().parse().unwrap();
+        let dropped = parsed_packet
+            .truncate_to(data.len() - 16, TruncationPolicy::default())
+            .unwrap();
+        assert_eq!(dropped.answers, 1);
+        assert!(parsed_packet.tc());
+        assert!(parsed_packet.maybe_compressed);
+        assert_eq!(parsed_packet.edns_count, 1);
+        assert!(parsed_packet.dnssec_ok());
+        let truncated = parsed_packet.into_packet();
+        assert_eq!(truncated.len(), data.len() - 16);
+        assert_eq!(&truncated[..2], &[0xbe, 0xef]);
+        assert_eq!(&truncated[truncated.len() - 15..], &data[data.len() - 15..]);
+        DNSSector::new(truncated).unwrap().parse().unwrap();
+    }
+    #[test]
+    fn test_ttls() {
+        let mut data: Vec<u8> = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1];
+        data.extend(b"\x07example\x00");
+        data.extend(vec![0, 1, 0, 1]);
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 0]);
+        let query = DNSSector::new(data).unwrap().parse().unwrap();
+        let mut response = query.to_response(ResponseOptions::default()).unwrap();
+        assert_eq!(response.min_ttl().unwrap(), None);
+        response
+            .insert_rr_from_string(
+                Section::NameServers,
+                "example. 3600 IN SOA ns.example. hostmaster.example. (1 7200 900 86400 300)",
+            )
+            .unwrap();
+        assert_eq!(response.min_ttl().unwrap(), Some(300));
+        response
+            .insert_rr_from_string(Section::Answer, "example. 30 IN A 192.0.2.1")
+            .unwrap();
+        response
+            .insert_rr_from_string(Section::Answer, "example. 86400 IN A 192.0.2.2")
+            .unwrap();
+        response
+            .insert_rr_from_string(Section::Additional, "ns.example. 10 IN A 192.0.2.3")
+            .unwrap();
+        assert_eq!(response.min_ttl().unwrap(), Some(10));
+
+        let ttls = |response: &mut ParsedPacket| {
+            let mut ttls = vec![];
+            let mut it = response.into_iter_answer();
+            while let Some(item) = it {
+                ttls.push(item.rr_ttl());
+                it = item.next();
+            }
+            let mut it = response.into_iter_nameservers();
+            while let Some(item) = it {
+                ttls.push(item.rr_ttl());
+                it = item.next();
+            }
+            let mut it = response.into_iter_additional();
+            while let Some(item) = it {
+                ttls.push(item.rr_ttl());
+                it = item.next();
+            }
+            ttls
+        };
+        response.clamp_ttls(60, 3000).unwrap();
+        assert_eq!(ttls(&mut response), vec![60, 3000, 3000, 60]);
+        assert!(response.dnssec_ok());
+
+        assert!(!response.decrement_ttls(59).unwrap());
+        assert_eq!(ttls(&mut response), vec![1, 2941, 2941, 1]);
+        assert!(response.decrement_ttls(1).unwrap());
+        assert_eq!(ttls(&mut response), vec![0, 2940, 2940, 0]);
+        assert!(response.decrement_ttls(5000).unwrap());
+        assert_eq!(ttls(&mut response), vec![0, 0, 0, 0]);
+        assert_eq!(response.min_ttl().unwrap(), Some(0));
+
+        // The extended flags are still there
+        let reparsed = DNSSector::new(response.into_packet())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(reparsed.dnssec_ok());
+        assert_eq!(reparsed.ext_flags, Some(0x8000));
+    }
+    #[test]
+    fn test_retain() {
+        let mut data: Vec<u8> = vec![0xbe, 0xef, 0x81, 0x80, 0, 1, 0, 2, 0, 1, 0, 2];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        // www.a CNAME a, followed by a record whose name points into it
+        data.extend(vec![
+            3, b'w', b'w', b'w', 0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 42, 0, 2, 0xc0, 12,
+        ]);
+        data.extend(vec![0xc0, 19, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+        data.extend(vec![0xc0, 19, 0, 2, 0, 1, 0, 0, 0, 42, 0, 2, 0xc0, 12]);
+        data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 0]);
+        data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 10, 0, 0, 1]);
+        let mut parsed_packet = DNSSector::new(data.clone()).unwrap().parse().unwrap();
+
+        // Nothing to remove
+        let dropped = parsed_packet.retain(|_| true).unwrap();
+        assert_eq!(dropped.total(), 0);
+        assert_eq!(parsed_packet.packet(), &data[..]);
+
+        let mut seen = vec![];
+        let dropped = parsed_packet
+            .retain(|rr| {
+                seen.push((rr.section(), rr.rr_type()));
+                rr.rr_type() == u16::from(Type::A)
+            })
+            .unwrap();
+        assert_eq!(
+            seen,
+            vec![
+                (Section::Answer, Type::CNAME.into()),
+                (Section::Answer, Type::A.into()),
+                (Section::NameServers, Type::NS.into()),
+                (Section::Additional, Type::A.into()),
+            ]
+        );
+        assert_eq!(dropped.answers, 1);
+        assert_eq!(dropped.nameservers, 1);
+        assert_eq!(dropped.additional, 0);
+        assert!(parsed_packet.offset_nameservers.is_none());
+        assert!(parsed_packet.dnssec_ok());
+
+        let reparsed = parsed_packet.into_packet();
+        let parsed_packet_ref = DNSSector::new(&reparsed[..]).unwrap().parse().unwrap();
+        let answers: Vec<_> = parsed_packet_ref
+            .iter_answer()
+            .map(|rr| (rr.name(), rr.rr_ip().unwrap()))
+            .collect();
+        assert_eq!(
+            answers,
+            vec![(b"www.a".to_vec(), IpAddr::from([1, 2, 3, 4]))]
+        );
+        assert_eq!(parsed_packet_ref.iter_nameservers().count(), 0);
+        let additional: Vec<_> = parsed_packet_ref
+            .iter_additional()
+            .map(|rr| rr.rr_ip().unwrap())
+            .collect();
+        assert_eq!(additional, vec![IpAddr::from([10, 0, 0, 1])]);
+        assert_eq!(parsed_packet_ref.ext_flags, Some(0x8000));
+
+        // Per-section variants leave the other sections alone
+        let mut parsed_packet = DNSSector::new(data).unwrap().parse().unwrap();
+        let dropped = parsed_packet.retain_additional(|_| false).unwrap();
+        assert_eq!(dropped.additional, 1);
+        assert_eq!(parsed_packet.ext_flags, Some(0x8000));
+        let reparsed = DNSSector::new(parsed_packet.into_packet())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(reparsed.offset_answers.is_some());
+        assert!(reparsed.dnssec_ok());
+    }
+    #[test]
+    fn test_strip_dnssec() {
+        let response = |qtype: u8| {
+            let mut data: Vec<u8> = vec![0xbe, 0xef, 0x81, 0xa0, 0, 1, 0, 3, 0, 1, 0, 2];
+            data.extend(vec![1, b'a', 0, 0, qtype, 0, 1]);
+            data.extend(vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+            data.extend(vec![0xc0, 12, 0, 46, 0, 1, 0, 0, 0, 42, 0, 21, 0, 1, 8, 1]);
+            data.extend(vec![0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 2]);
+            data.extend(vec![
+                0xc0, 12, 0, 43, 0, 1, 0, 0, 0, 42, 0, 5, 0, 2, 8, 2, 0xaa,
+            ]);
+            data.extend(vec![
+                0xc0, 12, 0, 47, 0, 1, 0, 0, 0, 42, 0, 4, 0, 0, 1, 0x40,
+            ]);
+            data.extend(vec![
+                0xc0, 12, 0, 48, 0, 1, 0, 0, 0, 42, 0, 5, 1, 0, 3, 8, 0xaa,
+            ]);
+            data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+            DNSSector::new(data).unwrap().parse().unwrap()
+        };
+        let types = |parsed_packet_ref: &ParsedPacketRef<'_>| {
+            parsed_packet_ref
+                .iter_answer()
+                .chain(parsed_packet_ref.iter_nameservers())
+                .chain(parsed_packet_ref.iter_additional())
+                .map(|rr| rr.rr_type())
+                .collect::<Vec<_>>()
+        };
+
+        let mut parsed_packet = response(1);
+        assert!(parsed_packet.ad());
+        let dropped = parsed_packet.strip_dnssec().unwrap();
+        assert_eq!(dropped.answers, 2);
+        assert_eq!(dropped.nameservers, 1);
+        assert_eq!(dropped.additional, 1);
+        assert!(!parsed_packet.ad());
+        let stripped = parsed_packet.into_packet();
+        let parsed_packet_ref = DNSSector::new(&stripped[..]).unwrap().parse().unwrap();
+        assert_eq!(types(&parsed_packet_ref), vec![1]);
+        assert_eq!(parsed_packet_ref.max_payload(), 4096);
+
+        // DS records are kept if they were asked for
+        let mut parsed_packet = response(43);
+        let dropped = parsed_packet.strip_dnssec().unwrap();
+        assert_eq!(dropped.total(), 3);
+        let stripped = parsed_packet.into_packet();
+        let parsed_packet_ref = DNSSector::new(&stripped[..]).unwrap().parse().unwrap();
+        assert_eq!(types(&parsed_packet_ref), vec![1, 43]);
+    }
+    #[test]
+    fn test_minimize() {
+        let response = |flags: u8| {
+            let mut data: Vec<u8> = vec![0xbe, 0xef, 0x81, flags, 0, 1, 0, 1, 0, 1, 0, 5];
+            data.extend(vec![1, b'a', 0, 0, 15, 0, 1]);
+            data.extend(vec![0xc0, 12, 0, 15, 0, 1, 0, 0, 0, 42, 0, 7]);
+            data.extend(vec![0, 10, 2, b'm', b'x', 0xc0, 12]);
+            data.extend(vec![0xc0, 12, 0, 2, 0, 1, 0, 0, 0, 42, 0, 5]);
+            data.extend(vec![2, b'n', b's', 0xc0, 12]);
+            data.extend(vec![0xc0, 33, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 1, 2, 3, 4]);
+            data.extend(vec![0xc0, 50, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 5, 6, 7, 8]);
+            data.extend(vec![1, b'b', 0, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4, 9, 9, 9, 9]);
+            data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+            data.extend(vec![1, b'k', 0, 0, 250, 0, 255, 0, 0, 0, 0, 0, 3, 0, 0, 0]);
+            DNSSector::new(data).unwrap().parse().unwrap()
+        };
+        let additional = |parsed_packet: ParsedPacket| {
+            let packet = parsed_packet.into_packet();
+            let parsed_packet_ref = DNSSector::new(&packet[..]).unwrap().parse().unwrap();
+            assert_eq!(parsed_packet_ref.max_payload(), 4096);
+            parsed_packet_ref
+                .iter_additional()
+                .map(|rr| rr.name())
+                .collect::<Vec<_>>()
+        };
+
+        let mut parsed_packet = response(0x80);
+        let dropped = parsed_packet.minimize(MinimalPolicy::all()).unwrap();
+        assert_eq!(dropped.answers, 0);
+        assert_eq!(dropped.nameservers, 1);
+        assert_eq!(dropped.additional, 2);
+        assert_eq!(
+            additional(parsed_packet),
+            vec![b"mx.a".to_vec(), b"k".to_vec()]
+        );
+
+        let mut parsed_packet = response(0x80);
+        let dropped = parsed_packet.minimize(MinimalPolicy::no_auth()).unwrap();
+        assert_eq!(dropped.nameservers, 1);
+        assert_eq!(dropped.additional, 0);
+
+        // Negative responses keep their authority section, and its glue
+        let mut parsed_packet = response(0x83);
+        let dropped = parsed_packet.minimize(MinimalPolicy::default()).unwrap();
+        assert_eq!(dropped.nameservers, 0);
+        assert_eq!(dropped.additional, 1);
+        assert_eq!(
+            additional(parsed_packet),
+            vec![b"mx.a".to_vec(), b"ns.a".to_vec(), b"k".to_vec()]
+        );
+    }
+    #[test]
+    fn test_shuffle_rrsets() {
+        let mut data: Vec<u8> = vec![0xbe, 0xef, 0x81, 0x80, 0, 1, 0, 5, 0, 0, 0, 0];
+        data.extend(vec![1, b'a', 0, 0, 1, 0, 1]);
+        data.extend(vec![0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 42, 0, 3, 1, b'b', 0]);
+        for ip in [[1, 1, 1, 1], [2, 2, 2, 2], [10, 0, 0, 1], [10, 0, 0, 2]] {
+            data.extend(vec![0xc0, 31, 0, 1, 0, 1, 0, 0, 0, 42, 0, 4]);
+            data.extend(ip);
+        }
+        let answers = |parsed_packet: &ParsedPacket| {
+            let packet = parsed_packet.packet().to_vec();
+            let parsed_packet_ref = DNSSector::new(&packet[..]).unwrap().parse().unwrap();
+            parsed_packet_ref
+                .iter_answer()
+                .map(|rr| match rr.rr_ip() {
+                    Ok(ip) => (rr.name(), ip.to_string()),
+                    Err(_) => (rr.name(), "CNAME".to_string()),
+                })
+                .collect::<Vec<_>>()
+        };
+        let ips = |parsed_packet: &ParsedPacket| {
+            let answers = answers(parsed_packet);
+            assert_eq!(answers[0], (b"a".to_vec(), "CNAME".to_string()));
+            assert!(answers[1..].iter().all(|(name, _)| name == b"b"));
+            answers[1..]
+                .iter()
+                .map(|(_, ip)| ip.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut parsed_packet = DNSSector::new(data.clone()).unwrap().parse().unwrap();
+        parsed_packet
+            .shuffle_rrsets(Section::Answer, ShuffleStrategy::Rotate(4))
+            .unwrap();
+        assert!(parsed_packet.maybe_compressed);
+        assert_eq!(parsed_packet.packet(), &data[..]);
+        parsed_packet
+            .shuffle_rrsets(Section::Answer, ShuffleStrategy::Rotate(5))
+            .unwrap();
+        assert_eq!(
+            ips(&parsed_packet),
+            vec!["2.2.2.2", "10.0.0.1", "10.0.0.2", "1.1.1.1"]
+        );
+
+        parsed_packet
+            .shuffle_rrsets(
+                Section::Answer,
+                ShuffleStrategy::Sortlist("10.0.0.9".parse().unwrap()),
+            )
+            .unwrap();
+        assert_eq!(
+            ips(&parsed_packet),
+            vec!["10.0.0.1", "10.0.0.2", "2.2.2.2", "1.1.1.1"]
+        );
+
+        parsed_packet
+            .shuffle_rrsets(Section::Answer, ShuffleStrategy::Random)
+            .unwrap();
+        let mut shuffled = ips(&parsed_packet);
+        shuffled.sort();
+        assert_eq!(shuffled, vec!["1.1.1.1", "10.0.0.1", "10.0.0.2", "2.2.2.2"]);
+    }
+    #[test]
+    fn test_flatten_cname_chain() {
+        let raw_name = |name: &str| {
+            let mut raw = vec![];
+            for label in name.split('.') {
+                raw.push(label.len() as u8);
+                raw.extend(label.as_bytes());
+            }
+            raw.push(0);
+            raw
+        };
+        let rr = |name: &str, rr_type: u8, ttl: u8, rdata: &[u8]| {
+            let mut rr = raw_name(name);
+            rr.extend(vec![0, rr_type, 0, 1, 0, 0, 0, ttl, 0, rdata.len() as u8]);
+            rr.extend(rdata);
+            rr
+        };
+        let mut rrsig = vec![0, 5, 8, 2, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        rrsig.extend(vec![0, 1, 2]);
+        let response = |answers: &[Vec<u8>]| {
+            let mut data: Vec<u8> = vec![0xbe, 0xef, 0x81, 0x80, 0, 1, 0, answers.len() as u8];
+            data.extend(vec![0, 1, 0, 1]);
+            data.extend(raw_name("WWW.example"));
+            data.extend(vec![0, 1, 0, 1]);
+            for answer in answers {
+                data.extend(answer);
+            }
+            data.extend(rr("example", 2, 200, &raw_name("ns.y.example")));
+            data.extend(vec![0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+            let data = Compress::compress(&data).unwrap();
+            DNSSector::new(data).unwrap().parse().unwrap()
+        };
+
+        let mut parsed_packet = response(&[
+            rr("www.example", 5, 100, &raw_name("x.example")),
+            rr("www.example", 46, 100, &rrsig),
+            rr("x.example", 5, 30, &raw_name("y.example")),
+            rr("y.example", 1, 200, &[1, 2, 3, 4]),
+            rr("y.example", 1, 200, &[5, 6, 7, 8]),
+            rr("y.example", 46, 200, &rrsig),
+        ]);
+        assert!(parsed_packet.maybe_compressed);
+        assert!(parsed_packet.flatten_cname_chain().unwrap());
+        let packet = parsed_packet.into_packet();
+        let parsed_packet_ref = DNSSector::new(&packet[..]).unwrap().parse().unwrap();
+        let answers: Vec<_> = parsed_packet_ref
+            .iter_answer()
+            .map(|rr| {
+                (
+                    rr.name_slice().to_vec(),
+                    rr.rr_ttl().unwrap(),
+                    rr.rr_ip().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            answers,
+            vec![
+                (raw_name("WWW.example"), 30, IpAddr::from([1, 2, 3, 4])),
+                (raw_name("WWW.example"), 30, IpAddr::from([5, 6, 7, 8])),
+            ]
+        );
+        let nameservers: Vec<_> = parsed_packet_ref
+            .iter_nameservers()
+            .map(|rr| rr.rdata().unwrap().to_vec())
+            .collect();
+        assert_eq!(nameservers, vec![raw_name("ns.y.example")]);
+        assert_eq!(parsed_packet_ref.max_payload(), 4096);
+
+        // Nothing to flatten
+        let mut parsed_packet = response(&[rr("www.example", 1, 100, &[1, 2, 3, 4])]);
+        assert!(!parsed_packet.flatten_cname_chain().unwrap());
+
+        // Loops and dangling chains
+        let mut parsed_packet = response(&[
+            rr("www.example", 5, 100, &raw_name("x.example")),
+            rr("x.example", 5, 100, &raw_name("WWW.example")),
+        ]);
+        let packet = parsed_packet.packet().to_vec();
+        assert!(parsed_packet.flatten_cname_chain().is_err());
+        assert_eq!(parsed_packet.packet(), &packet[..]);
+        let mut parsed_packet = response(&[
+            rr("www.example", 5, 100, &raw_name("x.example")),
+            rr("x.example", 16, 100, &[3, b'f', b'o', b'o']),
+        ]);
+        assert!(parsed_packet.flatten_cname_chain().is_err());
+    }
+    #[test]
+    fn test_ip_prefix() {
+        let prefix: IpPrefix = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(prefix.to_string(), "10.0.0.0/8");
+        assert_eq!(prefix.addr(), IpAddr::from([10, 0, 0, 0]));
+        assert_eq!(prefix.prefix_len(), 8);
+        assert!(prefix.contains(&"10.255.0.1".parse().unwrap()));
+        assert!(!prefix.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(!prefix.contains(&"::a01:203".parse().unwrap()));
+        let prefix: IpPrefix = "2001:db8::1".parse().unwrap();
+        assert_eq!(prefix.prefix_len(), 128);
+        assert!(prefix.contains(&"2001:db8::1".parse().unwrap()));
+        let prefix: IpPrefix = "::/0".parse().unwrap();
+        assert!(prefix.contains(&"2001:db8::1".parse().unwrap()));
+        let prefix: IpPrefix = "0.0.0.0/0".parse().unwrap();
+        assert!(prefix.contains(&"192.0.2.1".parse().unwrap()));
+        assert!(IpPrefix::new(IpAddr::from([1, 2, 3, 4]), 33).is_err());
+        assert!("1.2.3.4/33".parse::<IpPrefix>().is_err());
+        assert!("1.2.3/8".parse::<IpPrefix>().is_err());
+        assert!("1.2.3.4/x".parse::<IpPrefix>().is_err());
+    }
+
+    #[test]
+    fn test_dns64() {
+        // RFC 6052 section 2.4
+        let ipv4 = "192.0.2.33".parse().unwrap();
+        for (prefix, expected) in [
+            ("2001:db8::/32", "2001:db8:c000:221::"),
+            ("2001:db8:100::/40", "2001:db8:1c0:2:21::"),
+            ("2001:db8:122::/48", "2001:db8:122:c000:2:2100::"),
+            ("2001:db8:122:300::/56", "2001:db8:122:3c0:0:221::"),
+            ("2001:db8:122:344::/64", "2001:db8:122:344:c0:2:2100:0"),
+            ("2001:db8:122:344::/96", "2001:db8:122:344::c000:221"),
+        ] {
+            let dns64 = Dns64::new(prefix.parse().unwrap()).unwrap();
+            let ipv6 = dns64.synthesize_address(ipv4);
+            assert_eq!(ipv6, expected.parse::<std::net::Ipv6Addr>().unwrap());
+            assert_eq!(dns64.extract_ipv4(&ipv6), Some(ipv4));
+        }
+        assert!(Dns64::new("2001:db8::/33".parse().unwrap()).is_err());
+        assert!(Dns64::new("192.0.2.0/24".parse().unwrap()).is_err());
+
+        let dns64 = Dns64::well_known();
+        let aaaa_response = |rrs: &[&str], rcode: Rcode| {
+            let query = gen::query(b"www.example", Type::AAAA, Class::IN).unwrap();
+            let options = ResponseOptions::default().with_rcode(rcode);
+            let mut response = query.to_response(options).unwrap();
+            for rr in rrs {
+                response.insert_rr_from_string(Section::Answer, rr).unwrap();
+            }
+            response
+        };
+        assert!(dns64
+            .needs_synthesis(&mut aaaa_response(&[], Rcode::NOERROR))
+            .unwrap());
+        assert!(dns64
+            .needs_synthesis(&mut aaaa_response(&[], Rcode::SERVFAIL))
+            .unwrap());
+        assert!(!dns64
+            .needs_synthesis(&mut aaaa_response(&[], Rcode::NXDOMAIN))
+            .unwrap());
+        assert!(dns64
+            .needs_synthesis(&mut aaaa_response(
+                &["www.example. 60 IN AAAA ::ffff:c000:201"],
+                Rcode::NOERROR
+            ))
+            .unwrap());
+        assert!(!dns64
+            .needs_synthesis(&mut aaaa_response(
+                &["www.example. 60 IN AAAA 2001:db8::1"],
+                Rcode::NOERROR
+            ))
+            .unwrap());
+
+        let query = gen::query(b"www.example", Type::AAAA, Class::IN).unwrap();
+        let mut a_response = gen::query(b"www.example", Type::A, Class::IN)
+            .unwrap()
+            .into_response(ResponseOptions::default().with_recursion_available(true))
+            .unwrap();
+        for rr in [
+            "www.example. 300 IN CNAME web.example.",
+            "web.example. 60 IN A 93.184.216.34",
+            "web.example. 60 IN A 10.0.0.1",
+            "web.example. 60 IN A 192.0.2.33",
+        ] {
+            a_response
+                .insert_rr_from_string(Section::Answer, rr)
+                .unwrap();
+        }
+        let a_query = gen::query(b"www.example", Type::A, Class::IN).unwrap();
+        assert!(dns64.synthesize(&a_query, &mut a_response).is_err());
+        let response = dns64.synthesize(&query, &mut a_response).unwrap();
+        assert!(response.ra());
+        let packet = response.into_packet();
+        let parsed_packet_ref = DNSSector::new(&packet[..]).unwrap().parse().unwrap();
+        assert_eq!(
+            parsed_packet_ref.qtype_qclass(),
+            Some((Type::AAAA.into(), 1))
+        );
+        let answers: Vec<_> = parsed_packet_ref
+            .iter_answer()
+            .map(|rr| {
+                (
+                    rr.name(),
+                    rr.rr_type(),
+                    rr.rr_ttl().unwrap(),
+                    rr.rr_ip().ok(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            answers,
+            vec![
+                (b"www.example".to_vec(), Type::CNAME.into(), 300, None),
+                (
+                    b"web.example".to_vec(),
+                    Type::AAAA.into(),
+                    60,
+                    Some("64:ff9b::5db8:d822".parse().unwrap())
+                ),
+            ]
+        );
+
+        // Reverse queries
+        let ptr_name = "1.2.2.0.0.0.0.c.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.b.9.f.f.4.6.0.0.ip6.arpa";
+        assert_eq!(
+            dns64.ptr_target(ptr_name.as_bytes()),
+            Some(b"33.2.0.192.in-addr.arpa".to_vec())
+        );
+        assert_eq!(dns64.ptr_target(b"1.0.0.0.ip6.arpa"), None);
+        let mut query = gen::query(ptr_name.as_bytes(), Type::PTR, Class::IN).unwrap();
+        let response = dns64.synthesize_ptr(&mut query).unwrap().unwrap();
+        let packet = response.into_packet();
+        let parsed_packet_ref = DNSSector::new(&packet[..]).unwrap().parse().unwrap();
+        let answer = parsed_packet_ref.iter_answer().next().unwrap();
+        assert_eq!(answer.rr_type(), u16::from(Type::CNAME));
+        assert_eq!(
+            answer.rdata().unwrap(),
+            &gen::raw_name_from_str(b"33.2.0.192.in-addr.arpa", None).unwrap()[..]
+        );
+        let mut query = gen::query(b"www.example", Type::PTR, Class::IN).unwrap();
+        assert!(dns64.synthesize_ptr(&mut query).unwrap().is_none());
+    }
+    #[test]
+    fn test_address_mapping() {
+        let address_mapping = AddressMapping::from_table(
+            "# Public to internal addresses
+            203.0.113.0/24 10.0.0.0/24
+            203.0.113.128/25 10.1.0.0/25
+
+            2001:db8::/32 fd00::/32",
+        )
+        .unwrap();
+        assert_eq!(address_mapping.rules.len(), 3);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(
+            address_mapping.map(&ip("203.0.113.5")),
+            Some(ip("10.0.0.5"))
+        );
+        assert_eq!(
+            address_mapping.map(&ip("203.0.113.200")),
+            Some(ip("10.1.0.72"))
+        );
+        assert_eq!(address_mapping.map(&ip("198.51.100.1")), None);
+        assert_eq!(
+            address_mapping.unmap(&ip("fd00::1")),
+            Some(ip("2001:db8::1"))
+        );
+        assert!(AddressMapping::from_table("192.0.2.0/24 fd00::/24").is_err());
+        assert!(AddressMapping::from_table("192.0.2.0/24 10.0.0.0/8").is_err());
+        assert!(AddressMapping::from_table("192.0.2.0/24").is_err());
+
+        let mut response = gen::query(b"www.example", Type::A, Class::IN)
+            .unwrap()
+            .into_response(ResponseOptions::default())
+            .unwrap();
+        for (section, rr) in [
+            (Section::Answer, "www.example. 60 IN A 203.0.113.5"),
+            (Section::Answer, "www.example. 60 IN A 198.51.100.1"),
+            (Section::Answer, "www.example. 60 IN AAAA 2001:db8::1"),
+            (Section::Additional, "ns.example. 60 IN A 203.0.113.200"),
+        ] {
+            response.insert_rr_from_string(section, rr).unwrap();
+        }
+        assert_eq!(address_mapping.apply(&mut response).unwrap(), 3);
+        let packet = response.into_packet();
+        let parsed_packet_ref = DNSSector::new(&packet[..]).unwrap().parse().unwrap();
+        let ips: Vec<_> = parsed_packet_ref
+            .iter_answer()
+            .chain(parsed_packet_ref.iter_additional())
+            .map(|rr| rr.rr_ip().unwrap())
+            .collect();
+        assert_eq!(
+            ips,
+            vec![
+                ip("10.0.0.5"),
+                ip("198.51.100.1"),
+                ip("fd00::1"),
+                ip("10.1.0.72")
+            ]
+        );
+
+        // Reverse queries
+        let mut query = gen::query(b"5.0.0.10.in-addr.arpa", Type::PTR, Class::IN).unwrap();
+        assert!(address_mapping.rewrite_ptr_query(&mut query).unwrap());
+        assert_eq!(
+            query.question().unwrap().0,
+            b"5.113.0.203.in-addr.arpa".to_vec()
+        );
+        let mut response = query.into_response(ResponseOptions::default()).unwrap();
+        response
+            .insert_rr_from_string(
+                Section::Answer,
+                "5.113.0.203.in-addr.arpa. 60 IN PTR www.example.",
+            )
+            .unwrap();
+        assert!(address_mapping.rewrite_ptr_response(&mut response).unwrap());
+        assert_eq!(
+            response.question().unwrap().0,
+            b"5.0.0.10.in-addr.arpa".to_vec()
+        );
+        let packet = response.into_packet();
+        let parsed_packet_ref = DNSSector::new(&packet[..]).unwrap().parse().unwrap();
+        let answer = parsed_packet_ref.iter_answer().next().unwrap();
+        assert_eq!(answer.name(), b"5.0.0.10.in-addr.arpa".to_vec());
+        let mut query = gen::query(b"5.51.100.198.in-addr.arpa", Type::PTR, Class::IN).unwrap();
+        assert!(!address_mapping.rewrite_ptr_query(&mut query).unwrap());
+    }
+    #[test]
+    fn test_rebinding_guard() {
+        let response = || {
+            let query = cache_query(0x1234, b"\x04evil\x07example\x00", Some((0, &[])));
+            let mut response = query
+                .into_response(ResponseOptions::default().with_recursion_available(true))
+                .unwrap();
+            for (section, rr) in [
+                (Section::Answer, "evil.example. 60 IN A 10.0.0.1"),
+                (Section::Answer, "evil.example. 60 IN A 93.184.215.14"),
+                (Section::Answer, "evil.example. 60 IN AAAA fd00::1"),
+                (Section::Answer, "evil.example. 60 IN AAAA 2606:2800:21f::1"),
+                (Section::Additional, "ns.example. 60 IN A 127.0.0.1"),
+            ] {
+                response.insert_rr_from_string(section, rr).unwrap();
+            }
+            response
+        };
+        let guard = RebindingGuard::new();
+        assert!(guard.is_blocked(&"100.64.1.1".parse().unwrap()));
+        assert!(guard.is_blocked(&"fe80::1".parse().unwrap()));
+        assert!(guard.is_blocked(&"192.0.2.1".parse().unwrap()));
+        assert!(guard.is_blocked(&"2001:db8::1".parse().unwrap()));
+        assert!(!guard.is_blocked(&"93.184.215.14".parse().unwrap()));
+        // IPv4 addresses embedded into NAT64 and 6to4 addresses
+        assert!(guard.is_blocked(&"64:ff9b::a00:1".parse().unwrap()));
+        assert!(!guard.is_blocked(&"64:ff9b::5db8:d70e".parse().unwrap()));
+        assert!(guard.is_blocked(&"2002:a00:1::1".parse().unwrap()));
+        assert!(guard.is_blocked(&"2002:7f00:1:1::1".parse().unwrap()));
+        assert!(!guard.is_blocked(&"2002:5db8:d70e::1".parse().unwrap()));
+
+        let mut parsed_packet = response();
+        assert_eq!(guard.apply(&mut parsed_packet).unwrap(), 3);
+        let packet = parsed_packet.into_packet();
+        let parsed_packet_ref = DNSSector::new(&packet[..]).unwrap().parse().unwrap();
+        assert_eq!(parsed_packet_ref.typed_rcode(), Rcode::NOERROR);
+        let ips: Vec<_> = parsed_packet_ref
+            .iter_answer()
+            .chain(parsed_packet_ref.iter_additional())
+            .map(|rr| rr.rr_ip().unwrap().to_string())
+            .collect();
+        assert_eq!(ips, vec!["93.184.215.14", "2606:2800:21f::1"]);
+
+        // Allowed domains
+        let mut parsed_packet = response();
+        let guard = RebindingGuard::new().with_allowed_domain(b"Example.");
+        assert!(guard.is_allowed_domain(b"evil.example"));
+        assert!(!guard.is_allowed_domain(b"badexample"));
+        assert_eq!(guard.apply(&mut parsed_packet).unwrap(), 0);
+        assert_eq!(DNSSector::ancount(parsed_packet.packet()), 4);
+
+        for (action, rcode) in [
+            (RebindingAction::Refuse, Rcode::REFUSED),
+            (RebindingAction::NxDomain, Rcode::NXDOMAIN),
+        ] {
+            let mut parsed_packet = response();
+            let tid = parsed_packet.tid();
+            let guard = RebindingGuard::new().with_action(action);
+            assert_eq!(guard.apply(&mut parsed_packet).unwrap(), 3);
+            assert_eq!(parsed_packet.typed_rcode(), rcode);
+            assert_eq!(parsed_packet.tid(), tid);
+            assert!(parsed_packet.ra());
+            assert_eq!(DNSSector::ancount(parsed_packet.packet()), 0);
+            let it = parsed_packet.into_iter_edns().unwrap();
+            let raw = it.raw();
+            assert_eq!(&raw.packet[raw.offset..], &[0, 15, 0, 2, 0, 15]);
+        }
+    }
+    fn cache_query(tid: u16, raw_name: &[u8], edns: Option<(u16, &[u8])>) -> ParsedPacket {
+        let mut data: Vec<u8> = vec![(tid >> 8) as u8, tid as u8, 0x01, 0x00, 0, 1, 0, 0, 0, 0];
+        data.extend(vec![0, edns.is_some() as u8]);
+        data.extend(raw_name);
+        data.extend(vec![0, 1, 0, 1]);
+        if let Some((ext_flags, options)) = edns {
+            data.extend(vec![0, 0, 41, 0x10, 0, 0, 0]);
+            data.extend(vec![(ext_flags >> 8) as u8, ext_flags as u8]);
+            data.extend(vec![0, options.len() as u8]);
+            data.extend(options);
+        }
+        DNSSector::new(data).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn test_cache() {
+        let clock = ManualClock::new(1000);
+        let options = CacheOptions::default()
+            .with_capacity(2)
+            .with_max_negative_ttl(100);
+        let mut cache = Cache::with_clock(options, clock.clone());
+
+        let mut query = cache_query(1, b"\x07ExAmple\x00", None);
+        let mut response = query.to_response(ResponseOptions::default()).unwrap();
+        response
+            .insert_rr_from_string(Section::Answer, "example. 60 IN A 192.0.2.1")
+            .unwrap();
+        assert!(cache.insert(&mut query, &response).unwrap());
+        assert_eq!(cache.len(), 1);
+
+        // Hits use the TID and the case of the new query, and age TTLs
+        let mut query = cache_query(2, b"\x07EXAMPLE\x00", None);
+        clock.advance(20);
+        let mut cached = cache.lookup(&mut query).unwrap();
+        assert_eq!(cached.tid(), 2);
+        assert!(cached.echoes_question_case(b"\x07EXAMPLE\x00"));
+        assert_eq!(cached.into_iter_answer().unwrap().rr_ttl(), 40);
+
+        // DO and CD are part of the key
+        let mut query_do = cache_query(3, b"\x07example\x00", Some((0x8000, &[])));
+        assert!(cache.lookup(&mut query_do).is_none());
+        let mut query_cd = cache_query(3, b"\x07example\x00", None);
+        query_cd.set_cd(true);
+        assert!(cache.lookup(&mut query_cd).is_none());
+
+        // Expiration
+        clock.advance(40);
+        assert!(cache.lookup(&mut query).is_none());
+        assert!(cache.is_empty());
+
+        // Negative caching requires a SOA record, and its TTL is capped
+        let mut query = cache_query(4, b"\x07missing\x07example\x00", None);
+        let options = ResponseOptions::default().with_rcode(Rcode::NXDOMAIN);
+        let response = query.to_response(options).unwrap();
+        assert!(!cache.insert(&mut query, &response).unwrap());
+        let soa = gen::RR::from_string(
+            "example. 3600 IN SOA ns.example. hostmaster.example. (1 7200 900 86400 300)",
+        )
+        .unwrap();
+        let response = query.to_nxdomain(soa, ResponseOptions::default()).unwrap();
+        assert!(cache.insert(&mut query, &response).unwrap());
+        clock.advance(99);
+        let mut cached = cache.lookup(&mut query).unwrap();
+        assert_eq!(cached.typed_rcode(), Rcode::NXDOMAIN);
+        assert_eq!(cached.into_iter_nameservers().unwrap().rr_ttl(), 1);
+        clock.advance(1);
+        assert!(cache.lookup(&mut query).is_none());
+
+        // Least recently used entries are evicted first
+        let insert = |cache: &mut Cache<ManualClock>, raw_name: &[u8]| {
+            let mut query = cache_query(5, raw_name, None);
+            let mut response = query.to_response(ResponseOptions::default()).unwrap();
+            response
+                .insert_rr_from_string(Section::Answer, "x. 60 IN A 192.0.2.1")
+                .unwrap();
+            assert!(cache.insert(&mut query, &response).unwrap());
+        };
+        insert(&mut cache, b"\x01a\x00");
+        insert(&mut cache, b"\x01b\x00");
+        assert!(cache
+            .lookup(&mut cache_query(6, b"\x01a\x00", None))
+            .is_some());
+        insert(&mut cache, b"\x01c\x00");
+        assert_eq!(cache.len(), 2);
+        assert!(cache
+            .lookup(&mut cache_query(6, b"\x01b\x00", None))
+            .is_none());
+        assert!(cache
+            .lookup(&mut cache_query(6, b"\x01a\x00", None))
+            .is_some());
+        assert!(cache
+            .lookup(&mut cache_query(6, b"\x01c\x00", None))
+            .is_some());
+    }
+
+    #[test]
+    fn test_cache_client_subnet() {
+        let clock = ManualClock::new(0);
+        let options = CacheOptions::default()
+            .with_capacity(1)
+            .with_client_subnet(true);
+        let mut cache = Cache::with_clock(options, clock.clone());
+        let ecs = |address: [u8; 3], scope_prefix: u8| {
+            let mut option = vec![0, 8, 0, 7, 0, 1, 24, scope_prefix];
+            option.extend(address);
+            option
+        };
+
+        let mut query = cache_query(1, b"\x07example\x00", Some((0, &ecs([10, 1, 2], 0))));
+        let mut response = query.to_response(ResponseOptions::default()).unwrap();
+        response
+            .add_edns_option(EdnsOption::CLIENT_SUBNET.into(), &ecs([10, 1, 2], 16)[4..])
+            .unwrap();
+        response
+            .insert_rr_from_string(Section::Answer, "example. 60 IN A 192.0.2.1")
+            .unwrap();
+        assert!(cache.insert(&mut query, &response).unwrap());
+        assert_eq!(cache.client_subnet_scopes(), vec![(1, 16)]);
+
+        let mut same_scope = cache_query(2, b"\x07example\x00", Some((0, &ecs([10, 1, 99], 0))));
+        assert!(cache.lookup(&mut same_scope).is_some());
+        let mut other_scope = cache_query(3, b"\x07example\x00", Some((0, &ecs([10, 2, 2], 0))));
+        assert!(cache.lookup(&mut other_scope).is_none());
+        let mut no_ecs = cache_query(4, b"\x07example\x00", None);
+        assert!(cache.lookup(&mut no_ecs).is_none());
+
+        // Scopes are forgotten along with the last entry using them
+        clock.advance(60);
+        assert_eq!(cache.remove_expired(), 1);
+        assert!(cache.client_subnet_scopes().is_empty());
+        assert!(cache.insert(&mut query, &response).unwrap());
+        assert_eq!(cache.client_subnet_scopes(), vec![(1, 16)]);
+        let mut other_name = cache_query(5, b"\x05other\x00", None);
+        let mut response = other_name.to_response(ResponseOptions::default()).unwrap();
+        response
+            .insert_rr_from_string(Section::Answer, "other. 60 IN A 192.0.2.2")
+            .unwrap();
+        assert!(cache.insert(&mut other_name, &response).unwrap());
+        assert_eq!(cache.len(), 1);
+        assert!(cache.client_subnet_scopes().is_empty());
+    }
+    #[test]
+    fn test_cache_serve_stale() {
+        let clock = ManualClock::new(0);
+        let options = CacheOptions::default().with_serve_stale(3600, DEFAULT_STALE_TTL);
+        let mut cache = Cache::with_clock(options, clock.clone());
+        let mut query = cache_query(1, b"\x07example\x00", Some((0, &[])));
+        let mut response = query.to_response(ResponseOptions::default()).unwrap();
+        response
+            .insert_rr_from_string(Section::Answer, "example. 60 IN A 192.0.2.1")
+            .unwrap();
+        assert!(cache.insert(&mut query, &response).unwrap());
+
+        clock.advance(10);
+        assert_eq!(
+            cache.staleness(&mut query),
+            Some(Staleness::Fresh { ttl: 50 })
+        );
+        let (_, staleness) = cache.lookup_stale(&mut query).unwrap();
+        assert!(!staleness.is_stale());
+
+        // Expired responses are only returned by `lookup_stale()`
+        clock.advance(60);
+        assert!(cache.lookup(&mut query).is_none());
+        let (mut stale, staleness) = cache.lookup_stale(&mut query).unwrap();
+        assert_eq!(
+            staleness,
+            Staleness::Stale {
+                expired_for: 10,
+                remaining: 3590
+            }
+        );
+        assert_eq!(
+            stale.into_iter_answer().unwrap().rr_ttl(),
+            DEFAULT_STALE_TTL
+        );
+        assert_eq!(stale.edns_count, 1);
+        {
+            let it = stale.into_iter_edns().unwrap();
+            let raw = it.raw();
+            assert_eq!(&raw.packet[raw.offset..], &[0, 15, 0, 2, 0, 3]);
+        }
+        assert_eq!(cache.remove_expired(), 0);
+
+        // Past the stale window, entries are gone
+        clock.advance(3590);
+        assert!(cache.lookup_stale(&mut query).is_none());
+        assert!(cache.is_empty());
+    }
 }
@@ -1,10 +1,6 @@
-use dnssector;
-
 mod tests {
-    use hex;
-
-    use super::dnssector::constants::*;
-    use super::dnssector::synth::r#gen::{self, RR};
+    use dnssector::constants::*;
+    use dnssector::synth::r#gen::{self, RR};
 
     #[test]
     fn test_gen_a() {